@@ -0,0 +1,38 @@
+//! Instructions per second of the reference interpreter and the [`Engine`]
+//! on `6/pong/Pong.hack`, run with `cargo bench`.
+
+use emulator::{load_program, Computer, Engine};
+
+use std::{path::Path, time::Instant};
+
+const CYCLES: u64 = 200_000_000;
+
+fn main() {
+    let program = load_program(Path::new("../../6/pong/Pong.hack")).expect("Pong.hack assembles");
+    let computer = Computer::new(&program);
+
+    let mut reference = computer.clone();
+    let start = Instant::now();
+    reference.run(CYCLES);
+    report("reference interpreter", reference.cycles, start);
+
+    let mut fast = computer.clone();
+    let start = Instant::now();
+    let mut engine = Engine::new(&fast.rom);
+    engine.run(&mut fast, CYCLES);
+    report("engine", fast.cycles, start);
+
+    assert_eq!(
+        (fast.pc, fast.a, fast.d),
+        (reference.pc, reference.a, reference.d)
+    );
+    assert!(fast.ram == reference.ram, "Engines disagree");
+}
+
+fn report(name: &str, cycles: u64, start: Instant) {
+    let seconds = start.elapsed().as_secs_f64();
+    println!(
+        "{name:22} {cycles} cycles in {seconds:.2} s, {:.0} million per second",
+        cycles as f64 / seconds / 1e6
+    );
+}
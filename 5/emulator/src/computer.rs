@@ -0,0 +1,183 @@
+use assembler::ROM_SIZE;
+
+/// Number of words in the Hack data memory
+pub const RAM_SIZE: usize = 32768;
+/// Start of the screen memory map
+pub const SCREEN: u16 = 16384;
+/// Memory mapped keyboard register, holds the code of the pressed key
+pub const KBD: u16 = 24576;
+pub const SCREEN_WIDTH: usize = 512;
+pub const SCREEN_HEIGHT: usize = 256;
+
+/// The Hack computer: CPU, instruction memory and data memory.
+#[derive(Debug, Clone)]
+pub struct Computer {
+    pub rom: Box<[u16; ROM_SIZE]>,
+    /// Includes the screen and the keyboard register
+    pub ram: Box<[u16; RAM_SIZE]>,
+    pub a: u16,
+    pub d: u16,
+    pub pc: u16,
+    /// Instructions executed so far
+    pub cycles: u64,
+}
+
+/// Why [`Computer::run`] returned.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stop {
+    /// The program reached a loop like `(END) @END 0;JMP`
+    Halted,
+    /// The given number of cycles was executed without halting
+    CycleLimit,
+}
+
+impl Computer {
+    /// Computer with `program` in the ROM and a zeroed RAM.
+    ///
+    /// Panics if `program` doesn't fit into the ROM.
+    pub fn new(program: &[u16]) -> Self {
+        assert!(
+            program.len() <= ROM_SIZE,
+            "Program has {} words, the ROM only fits {ROM_SIZE}",
+            program.len()
+        );
+        let mut rom = zeroed::<ROM_SIZE>();
+        rom[..program.len()].copy_from_slice(program);
+        Computer {
+            rom,
+            ram: zeroed(),
+            a: 0,
+            d: 0,
+            pc: 0,
+            cycles: 0,
+        }
+    }
+
+    /// Like the reset button, only the PC is cleared
+    pub fn reset(&mut self) {
+        self.pc = 0;
+    }
+
+    /// Executes the instruction at PC
+    pub fn step(&mut self) {
+        let instruction = self.rom[usize::from(self.pc)];
+        self.cycles += 1;
+        if instruction & 0x8000 == 0 {
+            self.a = instruction;
+            self.pc = next(self.pc);
+            return;
+        }
+        let address = self.a;
+        let y = if instruction & 0x1000 != 0 {
+            self.ram[memory_index(address)]
+        } else {
+            self.a
+        };
+        let out = alu(self.d, y, instruction >> 6);
+        if instruction & 0b001_000 != 0 {
+            self.write(address, out);
+        }
+        if instruction & 0b100_000 != 0 {
+            self.a = out;
+        }
+        if instruction & 0b010_000 != 0 {
+            self.d = out;
+        }
+        let out = out as i16;
+        let jump = (instruction & 0b100 != 0 && out < 0)
+            || (instruction & 0b010 != 0 && out == 0)
+            || (instruction & 0b001 != 0 && out > 0);
+        // The PC loads the A register as it was before this instruction
+        self.pc = if jump {
+            address & 0x7FFF
+        } else {
+            next(self.pc)
+        };
+    }
+
+    /// Executes up to `max_cycles` instructions, stopping early at a halt loop
+    pub fn run(&mut self, max_cycles: u64) -> Stop {
+        for _ in 0..max_cycles {
+            if self.is_halted() {
+                return Stop::Halted;
+            }
+            self.step();
+        }
+        if self.is_halted() {
+            Stop::Halted
+        } else {
+            Stop::CycleLimit
+        }
+    }
+
+    /// Whether PC is inside a loop that only jumps to itself, i.e. `@X 0;JMP`
+    /// at address `X`. Any C-instruction that jumps unconditionally without
+    /// storing its result counts as `0;JMP`.
+    pub fn is_halted(&self) -> bool {
+        let halt_loop_at = |address: u16| {
+            self.rom[usize::from(address)] == address
+                && self.rom[usize::from(next(address))] & 0xE03F == 0xE007
+        };
+        halt_loop_at(self.pc) || (self.pc > 0 && self.a == self.pc - 1 && halt_loop_at(self.pc - 1))
+    }
+
+    /// Writes to the keyboard register are ignored, like in the hardware
+    fn write(&mut self, address: u16, value: u16) {
+        if memory_index(address) != usize::from(KBD) {
+            self.ram[memory_index(address)] = value;
+        }
+    }
+
+    /// Words of the screen memory map, 32 per row
+    pub fn screen(&self) -> &[u16] {
+        &self.ram[usize::from(SCREEN)..usize::from(KBD)]
+    }
+
+    /// Whether the pixel in column `x` and row `y` is black
+    pub fn pixel(&self, x: usize, y: usize) -> bool {
+        let word = self.screen()[y * SCREEN_WIDTH / 16 + x / 16];
+        word & 1 << (x % 16) != 0
+    }
+
+    /// Code of the currently pressed key, 0 if none
+    pub fn key(&self) -> u16 {
+        self.ram[usize::from(KBD)]
+    }
+
+    pub fn set_key(&mut self, code: u16) {
+        self.ram[usize::from(KBD)] = code;
+    }
+}
+
+/// The Hack ALU, `control` holds the `zx nx zy ny f no` bits in its lowest
+/// six bits. Also computes the combinations without a mnemonic.
+pub fn alu(x: u16, y: u16, control: u16) -> u16 {
+    let bit = |n: u16| control & 1 << n != 0;
+    let x = if bit(5) { 0 } else { x };
+    let x = if bit(4) { !x } else { x };
+    let y = if bit(3) { 0 } else { y };
+    let y = if bit(2) { !y } else { y };
+    let out = if bit(1) { x.wrapping_add(y) } else { x & y };
+    if bit(0) {
+        !out
+    } else {
+        out
+    }
+}
+
+/// Addresses are 15 bits wide, the highest bit of A is ignored
+fn memory_index(address: u16) -> usize {
+    usize::from(address & 0x7FFF)
+}
+
+/// Address after `pc`, the counter wraps around at the end of the ROM
+fn next(pc: u16) -> u16 {
+    (pc + 1) & 0x7FFF
+}
+
+fn zeroed<const N: usize>() -> Box<[u16; N]> {
+    vec![0; N]
+        .into_boxed_slice()
+        .try_into()
+        .expect("Length is N")
+}
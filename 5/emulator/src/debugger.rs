@@ -0,0 +1,440 @@
+use crate::{
+    computer::{Computer, RAM_SIZE},
+    load_with_symbols,
+    script::parse_value,
+    trace::{replay, Trace},
+};
+
+use assembler::{AsmError, Instruction, Jump, SymbolKind, SymbolTable, ROM_SIZE};
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt::Write,
+    path::Path,
+};
+
+/// Instructions shown before and after the PC by `list`
+const LIST_CONTEXT: u16 = 5;
+/// Instructions `back` can undo
+const HISTORY: usize = 100_000;
+
+const HELP: &str = "\
+break ADDRESS|LABEL    stop before executing the instruction (b)
+delete ADDRESS|LABEL   remove a breakpoint
+watch ADDRESS|SYMBOL   stop when the RAM word changes, e.g. `watch RAM[SP]`
+unwatch ADDRESS|SYMBOL remove a watchpoint
+step [N]               execute N instructions, 1 by default (s)
+continue               run until a breakpoint, watchpoint or halt (c)
+back [N]               undo N instructions, 1 by default, changes made by
+                       `set` stay
+registers              show A, D, PC and the segment pointers (r)
+list [ADDRESS|LABEL]   disassemble around the PC or the address (l)
+print ADDRESS|SYMBOL [N]  show N words of RAM, 1 by default (p)
+set ADDRESS|SYMBOL=VALUE  change a RAM word
+info                   show breakpoints and watchpoints
+quit                   leave the debugger (q)";
+
+/// Stops a program at breakpoints and changed RAM words, for the `--debug`
+/// command line debugger.
+///
+/// Labels and symbols come from the assembler's symbol table, so programs
+/// loaded from `*.asm` files can be debugged with names like `Main.main`.
+#[derive(Debug, Clone)]
+pub struct Debugger {
+    pub computer: Computer,
+    symbols: SymbolTable,
+    /// Names of the labels at each ROM address
+    labels: BTreeMap<u16, Vec<String>>,
+    breakpoints: BTreeSet<u16>,
+    /// Watched RAM addresses with the value they had after the last step
+    watchpoints: BTreeMap<u16, u16>,
+    /// `continue` gives up after this many cycles without stopping
+    pub max_cycles: u64,
+    /// The last instructions, for stepping back
+    history: Trace,
+}
+
+/// Why [`Debugger::step`] or [`Debugger::resume`] returned.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Event {
+    /// All requested instructions were executed
+    Stepped,
+    /// The PC reached a breakpoint, the instruction there wasn't executed yet
+    Breakpoint(u16),
+    Watchpoint {
+        address: u16,
+        old: u16,
+        new: u16,
+    },
+    /// The program reached a halt loop
+    Halted,
+    /// `max_cycles` were executed without stopping
+    CycleLimit,
+}
+
+impl Debugger {
+    pub fn new(computer: Computer, symbols: SymbolTable) -> Self {
+        let mut labels: BTreeMap<u16, Vec<String>> = BTreeMap::new();
+        for (name, symbol) in symbols.iter() {
+            if symbol.kind == SymbolKind::Label {
+                labels
+                    .entry(symbol.address)
+                    .or_default()
+                    .push(name.to_owned());
+            }
+        }
+        for names in labels.values_mut() {
+            names.sort();
+        }
+        Debugger {
+            history: Trace::new(&computer, HISTORY),
+            computer,
+            symbols,
+            labels,
+            breakpoints: BTreeSet::new(),
+            watchpoints: BTreeMap::new(),
+            max_cycles: 100_000_000,
+        }
+    }
+
+    /// Debugger for a `*.asm` file with its labels, or for a `*.hack` file
+    /// with only the predefined symbols
+    pub fn load(file: &Path) -> Result<Self, Vec<AsmError>> {
+        let (words, symbols) = load_with_symbols(file)?;
+        Ok(Debugger::new(Computer::new(&words), symbols))
+    }
+
+    /// ROM address of a number or label
+    pub fn rom_address(&self, text: &str) -> Result<u16, String> {
+        if let Ok(address) = text.parse::<u16>() {
+            if usize::from(address) >= ROM_SIZE {
+                return Err(format!("address {address} is outside of the ROM"));
+            }
+            return Ok(address);
+        }
+        match self.symbols.kind(text) {
+            Some(SymbolKind::Label) => Ok(self.symbols.get(text).expect("Known symbol")),
+            _ => Err(format!("unknown label `{text}`")),
+        }
+    }
+
+    /// RAM address of a number or symbol, `RAM[...]` around it is optional
+    pub fn ram_address(&self, text: &str) -> Result<u16, String> {
+        let text = text
+            .strip_prefix("RAM[")
+            .and_then(|t| t.strip_suffix(']'))
+            .unwrap_or(text);
+        let address = match text.parse::<u16>() {
+            Ok(address) => address,
+            Err(_) => match self.symbols.kind(text) {
+                Some(SymbolKind::Predefined | SymbolKind::Variable) => {
+                    self.symbols.get(text).expect("Known symbol")
+                }
+                _ => return Err(format!("unknown symbol `{text}`")),
+            },
+        };
+        if usize::from(address) >= RAM_SIZE {
+            return Err(format!("address {address} is outside of the RAM"));
+        }
+        Ok(address)
+    }
+
+    pub fn add_breakpoint(&mut self, address: u16) {
+        self.breakpoints.insert(address);
+    }
+
+    pub fn remove_breakpoint(&mut self, address: u16) -> bool {
+        self.breakpoints.remove(&address)
+    }
+
+    pub fn add_watchpoint(&mut self, address: u16) {
+        let value = self.computer.ram[usize::from(address)];
+        self.watchpoints.insert(address, value);
+    }
+
+    pub fn remove_watchpoint(&mut self, address: u16) -> bool {
+        self.watchpoints.remove(&address).is_some()
+    }
+
+    /// Replays a trace from the current state, see [`replay`], keeping its
+    /// last instructions for stepping back
+    pub fn replay(&mut self, trace: &str) -> Result<(), String> {
+        self.history = replay(&mut self.computer, trace, HISTORY)?;
+        self.update_watchpoints();
+        Ok(())
+    }
+
+    /// Undoes up to `count` instructions, returns how many were undone
+    pub fn step_back(&mut self, count: u64) -> u64 {
+        let mut undone = 0;
+        while undone < count && self.history.step_back(&mut self.computer) {
+            undone += 1;
+        }
+        self.update_watchpoints();
+        undone
+    }
+
+    /// Watchpoints only trigger on changes made after this
+    fn update_watchpoints(&mut self) {
+        for (address, value) in &mut self.watchpoints {
+            *value = self.computer.ram[usize::from(*address)];
+        }
+    }
+
+    /// Executes `count` instructions, stopping early at breakpoints,
+    /// watchpoints and halt loops. A breakpoint at the PC is stepped over.
+    pub fn step(&mut self, count: u64) -> Event {
+        self.run_for(count).unwrap_or(Event::Stepped)
+    }
+
+    /// Runs until a breakpoint, watchpoint or halt loop, at most `max_cycles`
+    pub fn resume(&mut self) -> Event {
+        self.run_for(self.max_cycles).unwrap_or(Event::CycleLimit)
+    }
+
+    fn run_for(&mut self, count: u64) -> Option<Event> {
+        for i in 0..count {
+            if i > 0 {
+                if let Some(event) = self.breakpoint() {
+                    return Some(event);
+                }
+            }
+            if let Some(event) = self.single_step() {
+                return Some(event);
+            }
+        }
+        None
+    }
+
+    pub fn has_breakpoint(&self, address: u16) -> bool {
+        self.breakpoints.contains(&address)
+    }
+
+    fn breakpoint(&self) -> Option<Event> {
+        let pc = self.computer.pc;
+        self.breakpoints
+            .contains(&pc)
+            .then_some(Event::Breakpoint(pc))
+    }
+
+    fn single_step(&mut self) -> Option<Event> {
+        if self.computer.is_halted() {
+            return Some(Event::Halted);
+        }
+        self.history.step(&mut self.computer);
+        for (address, old) in &mut self.watchpoints {
+            let new = self.computer.ram[usize::from(*address)];
+            if new != *old {
+                let event = Event::Watchpoint {
+                    address: *address,
+                    old: *old,
+                    new,
+                };
+                *old = new;
+                return Some(event);
+            }
+        }
+        None
+    }
+
+    /// `label+offset` of the closest label at or before `address`
+    pub fn location(&self, address: u16) -> String {
+        match self.labels.range(..=address).next_back() {
+            Some((start, names)) if *start == address => names[0].clone(),
+            Some((start, names)) => format!("{}+{}", names[0], address - start),
+            None => address.to_string(),
+        }
+    }
+
+    /// A, D, PC and the pointers of the VM segments
+    pub fn registers(&self) -> String {
+        let c = &self.computer;
+        let mut text = format!(
+            "A  = {:6}  D = {:6}  PC = {} ({})  cycles = {}\n",
+            c.a as i16,
+            c.d as i16,
+            c.pc,
+            self.location(c.pc),
+            c.cycles
+        );
+        for (i, name) in ["SP", "LCL", "ARG", "THIS", "THAT"].into_iter().enumerate() {
+            write!(text, "{name} = {}  ", c.ram[i] as i16).expect("Writing to a String");
+        }
+        text.truncate(text.trim_end().len());
+        text.push('\n');
+        text
+    }
+
+    /// Disassembly of the instructions around `center`, with the labels in
+    /// between. `=>` marks the PC and `*` breakpoints.
+    pub fn list(&self, center: u16) -> String {
+        let start = center.saturating_sub(LIST_CONTEXT);
+        let end = (center + LIST_CONTEXT).min(0x7FFF);
+        let mut text = String::new();
+        for address in start..=end {
+            for name in self.labels.get(&address).into_iter().flatten() {
+                text.push_str(&format!("({name})\n"));
+            }
+            let marker = if address == self.computer.pc {
+                "=>"
+            } else {
+                "  "
+            };
+            let breakpoint = if self.breakpoints.contains(&address) {
+                '*'
+            } else {
+                ' '
+            };
+            let word = self.computer.rom[usize::from(address)];
+            text.push_str(&format!(
+                "{marker}{breakpoint}{address:5}  {}\n",
+                self.instruction(address, word)
+            ));
+        }
+        text
+    }
+
+    /// Decoded `word`. A-instructions loading a jump target show the label.
+    fn instruction(&self, address: u16, word: u16) -> String {
+        let Some(instruction) = Instruction::decode(word) else {
+            return format!("{word:016b}  // not an instruction");
+        };
+        let next = self.computer.rom[usize::from(address + 1) % self.computer.rom.len()];
+        let jumps = matches!(
+            Instruction::decode(next),
+            Some(Instruction::C { jump, .. }) if jump != Jump::Null
+        );
+        match self.labels.get(&word) {
+            Some(names) if jumps && word & 0x8000 == 0 => {
+                format!("{:<12}// {}", instruction.to_string(), names[0])
+            }
+            _ => instruction.to_string(),
+        }
+    }
+
+    /// Runs a command of the debugger prompt and returns what it prints
+    pub fn execute(&mut self, line: &str) -> Result<String, String> {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let count = |word: Option<&&str>| {
+            word.map_or(Ok(1), |w| {
+                w.parse::<u64>()
+                    .map_err(|_| format!("expected a count, got `{w}`"))
+            })
+        };
+        Ok(match words[..] {
+            ["break" | "b", target] => {
+                let address = self.rom_address(target)?;
+                self.add_breakpoint(address);
+                format!("breakpoint at {address} ({})\n", self.location(address))
+            }
+            ["delete", target] => {
+                let address = self.rom_address(target)?;
+                if !self.remove_breakpoint(address) {
+                    return Err(format!("no breakpoint at {address}"));
+                }
+                String::new()
+            }
+            ["watch", target] => {
+                let address = self.ram_address(target)?;
+                self.add_watchpoint(address);
+                format!(
+                    "watching RAM[{address}] = {}\n",
+                    self.computer.ram[usize::from(address)] as i16
+                )
+            }
+            ["unwatch", target] => {
+                let address = self.ram_address(target)?;
+                if !self.remove_watchpoint(address) {
+                    return Err(format!("RAM[{address}] isn't watched"));
+                }
+                String::new()
+            }
+            ["step" | "s", ref rest @ ..] if rest.len() <= 1 => {
+                let event = self.step(count(rest.first())?);
+                self.report(event)
+            }
+            ["continue" | "c"] => {
+                let event = self.resume();
+                self.report(event)
+            }
+            ["back", ref rest @ ..] if rest.len() <= 1 => {
+                let count = count(rest.first())?;
+                let undone = self.step_back(count);
+                let message = if undone < count {
+                    format!(
+                        "history starts at cycle {}, {undone} instructions undone\n",
+                        self.computer.cycles
+                    )
+                } else {
+                    String::new()
+                };
+                message + &self.report(Event::Stepped)
+            }
+            ["registers" | "r"] => self.registers(),
+            ["list" | "l"] => self.list(self.computer.pc),
+            ["list" | "l", target] => self.list(self.rom_address(target)?),
+            ["print" | "p", target, ref rest @ ..] if rest.len() <= 1 => {
+                let address = self.ram_address(target)?;
+                let count = count(rest.first())?;
+                let end = (u64::from(address) + count).min(RAM_SIZE as u64);
+                (u64::from(address)..end)
+                    .map(|a| format!("RAM[{a}] = {}\n", self.computer.ram[a as usize] as i16))
+                    .collect()
+            }
+            ["set", assignment] => {
+                let (target, value) = assignment
+                    .split_once('=')
+                    .ok_or("expected `set ADDRESS=VALUE`")?;
+                let address = self.ram_address(target)?;
+                let value = parse_value(value).map_err(|e| e.to_string())?;
+                self.computer.ram[usize::from(address)] = value;
+                // Changes made here don't trigger the watchpoint
+                if let Some(old) = self.watchpoints.get_mut(&address) {
+                    *old = value;
+                }
+                String::new()
+            }
+            ["info"] => self.info(),
+            ["help" | "h"] => format!("{HELP}\n"),
+            _ => return Err(format!("unknown command `{line}`, try `help`")),
+        })
+    }
+
+    fn info(&self) -> String {
+        let mut text = String::new();
+        for address in &self.breakpoints {
+            text.push_str(&format!(
+                "breakpoint at {address} ({})\n",
+                self.location(*address)
+            ));
+        }
+        for (address, value) in &self.watchpoints {
+            text.push_str(&format!("watching RAM[{address}] = {}\n", *value as i16));
+        }
+        if text.is_empty() {
+            text.push_str("no breakpoints or watchpoints\n");
+        }
+        text
+    }
+
+    /// What happened, followed by the instruction at the PC
+    fn report(&self, event: Event) -> String {
+        let pc = self.computer.pc;
+        let message = match event {
+            Event::Stepped => String::new(),
+            Event::Breakpoint(address) => format!("breakpoint at {address}\n"),
+            Event::Watchpoint { address, old, new } => format!(
+                "RAM[{address}] changed from {} to {}\n",
+                old as i16, new as i16
+            ),
+            Event::Halted => format!("halted after {} cycles\n", self.computer.cycles),
+            Event::CycleLimit => format!("no stop after {} cycles\n", self.max_cycles),
+        };
+        let word = self.computer.rom[usize::from(pc)];
+        format!(
+            "{message}{pc} ({}): {}\n",
+            self.location(pc),
+            self.instruction(pc, word)
+        )
+    }
+}
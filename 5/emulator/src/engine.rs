@@ -0,0 +1,285 @@
+use crate::{
+    computer::{alu, Computer, Stop, KBD},
+    keyboard::Timeline,
+};
+
+use assembler::ROM_SIZE;
+
+/// Longest block, so that runs with few cycles left rarely fall back to
+/// single steps
+const MAX_BLOCK_LEN: usize = 64;
+
+/// Fast execution engine for a [`Computer`].
+///
+/// The ROM is decoded into micro-ops once. Straight-line code from any
+/// address up to the next jump is cached as a basic block the first time it
+/// is entered, and then runs without checking for halt loops or jumps after
+/// every instruction. The state of the computer after [`Engine::run`] is the
+/// same as after [`Computer::run`], which serves as the reference.
+///
+/// The ROM is read when the engine is created, after changing it a new engine
+/// is needed.
+#[derive(Debug, Clone)]
+pub struct Engine {
+    /// Micro-ops of all blocks, each block is a range of this
+    ops: Vec<Op>,
+    /// Block starting at each address as `(first op, length)`, built on demand
+    blocks: Vec<Option<(u32, u32)>>,
+    /// Decoded ROM
+    rom: Vec<Op>,
+    /// Addresses that start a halt loop, blocks end before them
+    halt_loops: Vec<bool>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Op {
+    /// `@value`
+    A(u16),
+    /// C-instruction without a jump
+    C {
+        function: Alu,
+        y_is_m: bool,
+        dest: u8,
+    },
+    /// C-instruction with a jump, always the last op of a block
+    Jump {
+        function: Alu,
+        y_is_m: bool,
+        dest: u8,
+        /// The `j1 j2 j3` bits
+        condition: u8,
+    },
+}
+
+/// The ALU functions with a mnemonic, `Y` is A or M
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Alu {
+    Zero,
+    One,
+    MinusOne,
+    D,
+    Y,
+    NotD,
+    NotY,
+    NegD,
+    NegY,
+    DPlusOne,
+    YPlusOne,
+    DMinusOne,
+    YMinusOne,
+    DPlusY,
+    DMinusY,
+    YMinusD,
+    DAndY,
+    DOrY,
+    /// Any other `zx nx zy ny f no` combination
+    Other(u16),
+}
+
+const DEST_A: u8 = 0b100;
+const DEST_D: u8 = 0b010;
+const DEST_M: u8 = 0b001;
+
+impl Engine {
+    pub fn new(rom: &[u16; ROM_SIZE]) -> Self {
+        let halt_loops = (0..ROM_SIZE)
+            .map(|address| {
+                usize::from(rom[address]) == address
+                    && rom[(address + 1) % ROM_SIZE] & 0xE03F == 0xE007
+            })
+            .collect();
+        Engine {
+            ops: Vec::new(),
+            blocks: vec![None; ROM_SIZE],
+            rom: rom.iter().map(|word| decode(*word)).collect(),
+            halt_loops,
+        }
+    }
+
+    /// Executes up to `max_cycles` instructions, stopping early at a halt
+    /// loop, exactly like [`Computer::run`]
+    pub fn run(&mut self, computer: &mut Computer, max_cycles: u64) -> Stop {
+        let end = computer.cycles + max_cycles;
+        loop {
+            if computer.is_halted() {
+                return Stop::Halted;
+            }
+            let left = end - computer.cycles;
+            if left == 0 {
+                return Stop::CycleLimit;
+            }
+            let (first, len) = self.block(computer.pc);
+            if u64::from(len) > left {
+                // Not enough cycles left for the whole block
+                let op = self.rom[usize::from(computer.pc)];
+                execute(computer, op);
+                computer.cycles += 1;
+                continue;
+            }
+            for op in &self.ops[first as usize..(first + len) as usize] {
+                execute(computer, *op);
+            }
+            computer.cycles += u64::from(len);
+        }
+    }
+
+    /// Like [`Computer::run_with_keys`]
+    pub fn run_with_keys(
+        &mut self,
+        computer: &mut Computer,
+        max_cycles: u64,
+        timeline: &Timeline,
+    ) -> Stop {
+        computer.run_split(max_cycles, timeline, |computer, cycles| {
+            self.run(computer, cycles)
+        })
+    }
+
+    /// The block starting at `pc`, building it on the first visit
+    fn block(&mut self, pc: u16) -> (u32, u32) {
+        if let Some(block) = self.blocks[usize::from(pc)] {
+            return block;
+        }
+        let first = self.ops.len();
+        let mut address = usize::from(pc);
+        loop {
+            let op = self.rom[address];
+            self.ops.push(op);
+            address = (address + 1) % ROM_SIZE;
+            let len = self.ops.len() - first;
+            if matches!(op, Op::Jump { .. })
+                || len == MAX_BLOCK_LEN
+                || self.halt_loops[address]
+                || address == 0
+            {
+                break;
+            }
+        }
+        let block = (first as u32, (self.ops.len() - first) as u32);
+        self.blocks[usize::from(pc)] = Some(block);
+        block
+    }
+}
+
+/// Executes one micro-op, which also advances the PC
+#[inline(always)]
+fn execute(computer: &mut Computer, op: Op) {
+    match op {
+        Op::A(value) => {
+            computer.a = value;
+            computer.pc = (computer.pc + 1) & 0x7FFF;
+        }
+        Op::C {
+            function,
+            y_is_m,
+            dest,
+        } => {
+            compute(computer, function, y_is_m, dest);
+            computer.pc = (computer.pc + 1) & 0x7FFF;
+        }
+        Op::Jump {
+            function,
+            y_is_m,
+            dest,
+            condition,
+        } => {
+            let address = computer.a;
+            let out = compute(computer, function, y_is_m, dest) as i16;
+            let jump = (condition & 0b100 != 0 && out < 0)
+                || (condition & 0b010 != 0 && out == 0)
+                || (condition & 0b001 != 0 && out > 0);
+            computer.pc = if jump {
+                address & 0x7FFF
+            } else {
+                (computer.pc + 1) & 0x7FFF
+            };
+        }
+    }
+}
+
+/// Computes the ALU output and stores it, M at the A register as it was
+/// before the instruction
+#[inline(always)]
+fn compute(computer: &mut Computer, function: Alu, y_is_m: bool, dest: u8) -> u16 {
+    let address = usize::from(computer.a & 0x7FFF);
+    let d = computer.d;
+    let y = if y_is_m {
+        computer.ram[address]
+    } else {
+        computer.a
+    };
+    let out = match function {
+        Alu::Zero => 0,
+        Alu::One => 1,
+        Alu::MinusOne => 0xFFFF,
+        Alu::D => d,
+        Alu::Y => y,
+        Alu::NotD => !d,
+        Alu::NotY => !y,
+        Alu::NegD => d.wrapping_neg(),
+        Alu::NegY => y.wrapping_neg(),
+        Alu::DPlusOne => d.wrapping_add(1),
+        Alu::YPlusOne => y.wrapping_add(1),
+        Alu::DMinusOne => d.wrapping_sub(1),
+        Alu::YMinusOne => y.wrapping_sub(1),
+        Alu::DPlusY => d.wrapping_add(y),
+        Alu::DMinusY => d.wrapping_sub(y),
+        Alu::YMinusD => y.wrapping_sub(d),
+        Alu::DAndY => d & y,
+        Alu::DOrY => d | y,
+        Alu::Other(control) => alu(d, y, control),
+    };
+    if dest & DEST_M != 0 && address != usize::from(KBD) {
+        computer.ram[address] = out;
+    }
+    if dest & DEST_A != 0 {
+        computer.a = out;
+    }
+    if dest & DEST_D != 0 {
+        computer.d = out;
+    }
+    out
+}
+
+fn decode(word: u16) -> Op {
+    if word & 0x8000 == 0 {
+        return Op::A(word);
+    }
+    let control = (word >> 6) & 0b11_1111;
+    let function = match control {
+        0b101010 => Alu::Zero,
+        0b111111 => Alu::One,
+        0b111010 => Alu::MinusOne,
+        0b001100 => Alu::D,
+        0b110000 => Alu::Y,
+        0b001101 => Alu::NotD,
+        0b110001 => Alu::NotY,
+        0b001111 => Alu::NegD,
+        0b110011 => Alu::NegY,
+        0b011111 => Alu::DPlusOne,
+        0b110111 => Alu::YPlusOne,
+        0b001110 => Alu::DMinusOne,
+        0b110010 => Alu::YMinusOne,
+        0b000010 => Alu::DPlusY,
+        0b010011 => Alu::DMinusY,
+        0b000111 => Alu::YMinusD,
+        0b000000 => Alu::DAndY,
+        0b010101 => Alu::DOrY,
+        _ => Alu::Other(control),
+    };
+    let y_is_m = word & 0x1000 != 0;
+    let dest = ((word >> 3) & 0b111) as u8;
+    match (word & 0b111) as u8 {
+        0 => Op::C {
+            function,
+            y_is_m,
+            dest,
+        },
+        condition => Op::Jump {
+            function,
+            y_is_m,
+            dest,
+            condition,
+        },
+    }
+}
@@ -0,0 +1,82 @@
+use std::{
+    fmt::{self, Display},
+    path::PathBuf,
+};
+
+/// A problem found while running a test script, pointing back at the script
+/// line or, for comparison failures, at the line of the compare file. Also
+/// used for bad lines of keyboard timelines.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScriptError {
+    pub file: PathBuf,
+    /// `None` if the error is not tied to a line, e.g. an unreadable file
+    pub line: Option<usize>,
+    pub kind: ScriptErrorKind,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ScriptErrorKind {
+    Syntax(String),
+    UnknownVariable(String),
+    BadValue(String),
+    /// Command or file type that needs the hardware simulator
+    Unsupported(String),
+    /// Command that needs a program, but nothing was loaded
+    NothingLoaded,
+    /// The program or VM code couldn't be loaded
+    Load(String),
+    /// Output differs from the compare file, `column` is 1-based
+    Mismatch {
+        column: usize,
+        name: String,
+        expected: String,
+        actual: String,
+    },
+    /// The compare file has fewer lines than were output
+    ExtraOutput(String),
+    /// The script ended before all lines of the compare file were output
+    MissingOutput(String),
+    Io(String),
+}
+
+impl Display for ScriptError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:", self.file.display())?;
+        if let Some(line) = self.line {
+            write!(f, "{line}:")?;
+        }
+        write!(f, " error: {}", self.kind)
+    }
+}
+
+impl Display for ScriptErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ScriptErrorKind::Syntax(message) => f.write_str(message),
+            ScriptErrorKind::UnknownVariable(name) => write!(f, "unknown variable `{name}`"),
+            ScriptErrorKind::BadValue(text) => write!(f, "bad value `{text}`"),
+            ScriptErrorKind::Unsupported(what) => write!(f, "{what} is not supported"),
+            ScriptErrorKind::NothingLoaded => f.write_str("no program loaded"),
+            ScriptErrorKind::Load(message) => write!(f, "couldn't load program:\n{message}"),
+            ScriptErrorKind::Mismatch {
+                column,
+                name,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "comparison failure in column {column} `{name}`, \
+                expected `{expected}`, got `{actual}`"
+            ),
+            ScriptErrorKind::ExtraOutput(line) => {
+                write!(f, "compare file ended, but got `{line}`")
+            }
+            ScriptErrorKind::MissingOutput(line) => {
+                write!(f, "script ended, but expected `{line}`")
+            }
+            ScriptErrorKind::Io(e) => f.write_str(e),
+        }
+    }
+}
+
+impl std::error::Error for ScriptError {}
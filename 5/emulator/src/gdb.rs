@@ -0,0 +1,392 @@
+use crate::{
+    computer::RAM_SIZE,
+    debugger::{Debugger, Event},
+};
+
+use std::{
+    io::{self, ErrorKind, Read, Write},
+    net::TcpStream,
+};
+
+/// Instructions run by `continue` between checks for an interrupt
+const CHUNK: u64 = 100_000;
+
+/// Registers in the order of the `g` packet: A, D, PC, then the pointers in
+/// RAM[0] to RAM[4]
+pub const REGISTERS: [&str; 8] = ["a", "d", "pc", "sp", "lcl", "arg", "this", "that"];
+
+const TARGET_XML: &str = r#"<?xml version="1.0"?>
+<!DOCTYPE target SYSTEM "gdb-target.dtd">
+<target version="1.0">
+  <feature name="org.nand2tetris.hack">
+    <reg name="a" bitsize="16" type="int16" regnum="0"/>
+    <reg name="d" bitsize="16" type="int16"/>
+    <reg name="pc" bitsize="16" type="code_ptr"/>
+    <reg name="sp" bitsize="16" type="data_ptr"/>
+    <reg name="lcl" bitsize="16" type="data_ptr"/>
+    <reg name="arg" bitsize="16" type="data_ptr"/>
+    <reg name="this" bitsize="16" type="data_ptr"/>
+    <reg name="that" bitsize="16" type="data_ptr"/>
+  </feature>
+</target>
+"#;
+
+/// A stream a GDB client is connected through.
+pub trait Connection: Read + Write {
+    fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()>;
+}
+
+impl Connection for TcpStream {
+    fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        TcpStream::set_nonblocking(self, nonblocking)
+    }
+}
+
+#[cfg(unix)]
+impl Connection for std::os::unix::net::UnixStream {
+    fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        std::os::unix::net::UnixStream::set_nonblocking(self, nonblocking)
+    }
+}
+
+/// Serves the GDB remote serial protocol on `connection` until the client
+/// detaches, kills the program or disconnects.
+///
+/// Registers are 16 bits wide, see [`REGISTERS`]. Memory is the RAM with
+/// byte addresses, word `n` is at bytes `2n` and `2n + 1`, low byte first.
+/// Breakpoints are at ROM addresses, the values of the PC. `continue` stops
+/// at breakpoints, halt loops, after the debugger's `max_cycles` or when the
+/// client interrupts it.
+pub fn serve_gdb(debugger: &mut Debugger, connection: impl Connection) -> io::Result<()> {
+    let mut session = Session {
+        debugger,
+        connection,
+        acks: true,
+        last: Vec::new(),
+    };
+    while let Some(packet) = session.read_packet()? {
+        let reply = match session.handle(&packet)? {
+            Some(reply) => reply,
+            None => return Ok(()),
+        };
+        session.send(&reply)?;
+        match &packet[..] {
+            [b'D', ..] => return Ok(()),
+            // The reply is still acknowledged
+            b"QStartNoAckMode" => session.acks = false,
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+struct Session<'a, C> {
+    debugger: &'a mut Debugger,
+    connection: C,
+    /// Cleared by `QStartNoAckMode`
+    acks: bool,
+    /// Last packet sent, for resending it
+    last: Vec<u8>,
+}
+
+impl<C: Connection> Session<'_, C> {
+    fn read_byte(&mut self) -> io::Result<Option<u8>> {
+        let mut byte = [0];
+        loop {
+            return match self.connection.read(&mut byte) {
+                Ok(0) => Ok(None),
+                Ok(_) => Ok(Some(byte[0])),
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(e) => Err(e),
+            };
+        }
+    }
+
+    /// Data of the next valid packet, `None` at the end of the connection
+    fn read_packet(&mut self) -> io::Result<Option<Vec<u8>>> {
+        loop {
+            match self.read_byte()? {
+                None => return Ok(None),
+                Some(b'$') => {}
+                Some(b'-') => {
+                    let last = self.last.clone();
+                    self.connection.write_all(&last)?;
+                    continue;
+                }
+                // Acknowledgements and interrupts while stopped
+                Some(_) => continue,
+            }
+            let mut data = Vec::new();
+            let mut sum = 0u8;
+            let mut escaped = false;
+            loop {
+                let Some(byte) = self.read_byte()? else {
+                    return Ok(None);
+                };
+                if byte == b'#' {
+                    break;
+                }
+                sum = sum.wrapping_add(byte);
+                match (escaped, byte) {
+                    (false, b'}') => escaped = true,
+                    (true, _) => {
+                        data.push(byte ^ 0x20);
+                        escaped = false;
+                    }
+                    _ => data.push(byte),
+                }
+            }
+            let mut checksum = [0; 2];
+            for digit in &mut checksum {
+                *digit = self.read_byte()?.unwrap_or_default();
+            }
+            let valid = std::str::from_utf8(&checksum)
+                .ok()
+                .and_then(|c| u8::from_str_radix(c, 16).ok())
+                == Some(sum);
+            if self.acks {
+                self.connection.write_all(if valid { b"+" } else { b"-" })?;
+            }
+            if valid || !self.acks {
+                return Ok(Some(data));
+            }
+        }
+    }
+
+    fn send(&mut self, data: &str) -> io::Result<()> {
+        let sum = data.bytes().fold(0u8, |sum, byte| sum.wrapping_add(byte));
+        self.last = format!("${data}#{sum:02x}").into_bytes();
+        self.connection.write_all(&self.last)?;
+        self.connection.flush()
+    }
+
+    /// Reply to a packet, `None` to close the connection without one
+    fn handle(&mut self, packet: &[u8]) -> io::Result<Option<String>> {
+        let packet = String::from_utf8_lossy(packet);
+        let (command, args) = packet.split_at(packet.len().min(1));
+        let reply = match command {
+            "?" => "S05".to_owned(),
+            "g" => (0..REGISTERS.len())
+                .map(|n| hex_word(self.register(n)))
+                .collect(),
+            "G" => match words(args) {
+                Some(values) if values.len() == REGISTERS.len() => {
+                    for (n, value) in values.into_iter().enumerate() {
+                        self.set_register(n, value);
+                    }
+                    "OK".to_owned()
+                }
+                _ => "E01".to_owned(),
+            },
+            "p" => match usize::from_str_radix(args, 16) {
+                Ok(n) if n < REGISTERS.len() => hex_word(self.register(n)),
+                _ => "E01".to_owned(),
+            },
+            "P" => {
+                let register = args.split_once('=').and_then(|(n, value)| {
+                    let n = usize::from_str_radix(n, 16).ok()?;
+                    let value = words(value)?;
+                    (n < REGISTERS.len() && value.len() == 1).then(|| (n, value[0]))
+                });
+                match register {
+                    Some((n, value)) => {
+                        self.set_register(n, value);
+                        "OK".to_owned()
+                    }
+                    None => "E01".to_owned(),
+                }
+            }
+            "m" => match range(args) {
+                Some((start, len)) => (start..start + len)
+                    .map(|byte| format!("{:02x}", self.memory_byte(byte)))
+                    .collect(),
+                None => "E01".to_owned(),
+            },
+            "M" => {
+                let write = args.split_once(':').and_then(|(target, data)| {
+                    let (start, len) = range(target)?;
+                    let bytes = bytes(data)?;
+                    (bytes.len() == len).then_some((start, bytes))
+                });
+                match write {
+                    Some((start, bytes)) => {
+                        for (byte, value) in (start..).zip(bytes) {
+                            self.set_memory_byte(byte, value);
+                        }
+                        "OK".to_owned()
+                    }
+                    None => "E01".to_owned(),
+                }
+            }
+            "Z" | "z" => match breakpoint(args) {
+                Some(address) => {
+                    if command == "Z" {
+                        self.debugger.add_breakpoint(address);
+                    } else {
+                        self.debugger.remove_breakpoint(address);
+                    }
+                    "OK".to_owned()
+                }
+                // Only software breakpoints
+                None => String::new(),
+            },
+            "s" => {
+                self.debugger.step(1);
+                "S05".to_owned()
+            }
+            "c" => self.resume()?,
+            "H" => "OK".to_owned(),
+            "T" => "OK".to_owned(),
+            "k" => return Ok(None),
+            "D" => "OK".to_owned(),
+            "Q" if packet == "QStartNoAckMode" => "OK".to_owned(),
+            "q" | "Q" => self.query(&packet),
+            _ => String::new(),
+        };
+        Ok(Some(reply))
+    }
+
+    fn query(&self, packet: &str) -> String {
+        if packet.starts_with("qSupported") {
+            return "PacketSize=4000;qXfer:features:read+;QStartNoAckMode+".to_owned();
+        }
+        if let Some(window) = packet.strip_prefix("qXfer:features:read:target.xml:") {
+            let Some((offset, len)) = range(window) else {
+                return "E01".to_owned();
+            };
+            let start = offset.min(TARGET_XML.len());
+            let end = (start + len).min(TARGET_XML.len());
+            let marker = if end == TARGET_XML.len() { 'l' } else { 'm' };
+            return format!("{marker}{}", &TARGET_XML[start..end]);
+        }
+        match packet {
+            "qAttached" => "1".to_owned(),
+            "qC" => "QC1".to_owned(),
+            "qfThreadInfo" => "m1".to_owned(),
+            "qsThreadInfo" => "l".to_owned(),
+            _ => String::new(),
+        }
+    }
+
+    /// Continues in chunks, checking for the interrupt byte in between
+    fn resume(&mut self) -> io::Result<String> {
+        let end = self.debugger.computer.cycles + self.debugger.max_cycles;
+        loop {
+            let left = end - self.debugger.computer.cycles;
+            let event = self.debugger.step(CHUNK.min(left));
+            let pc = self.debugger.computer.pc;
+            if event != Event::Stepped
+                || self.debugger.has_breakpoint(pc)
+                || self.debugger.computer.cycles == end
+            {
+                return Ok("S05".to_owned());
+            }
+            if self.interrupted()? {
+                return Ok("S02".to_owned());
+            }
+        }
+    }
+
+    fn interrupted(&mut self) -> io::Result<bool> {
+        self.connection.set_nonblocking(true)?;
+        let mut byte = [0];
+        let read = self.connection.read(&mut byte);
+        self.connection.set_nonblocking(false)?;
+        match read {
+            Ok(1) => Ok(byte[0] == 0x03),
+            Ok(_) => Ok(false),
+            Err(e) if e.kind() == ErrorKind::WouldBlock => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn register(&self, n: usize) -> u16 {
+        let computer = &self.debugger.computer;
+        match n {
+            0 => computer.a,
+            1 => computer.d,
+            2 => computer.pc,
+            _ => computer.ram[n - 3],
+        }
+    }
+
+    fn set_register(&mut self, n: usize, value: u16) {
+        let computer = &mut self.debugger.computer;
+        match n {
+            0 => computer.a = value,
+            1 => computer.d = value,
+            2 => computer.pc = value & 0x7FFF,
+            _ => computer.ram[n - 3] = value,
+        }
+    }
+
+    /// Bytes past the RAM read as 0
+    fn memory_byte(&self, byte: usize) -> u8 {
+        let word = self.debugger.computer.ram.get(byte / 2).copied();
+        let word = word.unwrap_or_default();
+        if byte.is_multiple_of(2) {
+            word as u8
+        } else {
+            (word >> 8) as u8
+        }
+    }
+
+    fn set_memory_byte(&mut self, byte: usize, value: u8) {
+        if let Some(word) = self.debugger.computer.ram.get_mut(byte / 2) {
+            *word = if byte.is_multiple_of(2) {
+                (*word & 0xFF00) | u16::from(value)
+            } else {
+                (*word & 0x00FF) | (u16::from(value) << 8)
+            };
+        }
+    }
+}
+
+/// `ADDRESS,LENGTH` in hex, inside of the RAM
+fn range(text: &str) -> Option<(usize, usize)> {
+    let (start, len) = text.split_once(',')?;
+    let start = usize::from_str_radix(start, 16).ok()?;
+    let len = usize::from_str_radix(len, 16).ok()?;
+    let end = start.checked_add(len)?;
+    (end <= 2 * RAM_SIZE).then_some((start, len))
+}
+
+/// Address of a `0,ADDRESS,KIND` software breakpoint
+fn breakpoint(text: &str) -> Option<u16> {
+    let mut parts = text.split(',');
+    if parts.next()? != "0" {
+        return None;
+    }
+    let address = u16::from_str_radix(parts.next()?, 16).ok()?;
+    Some(address & 0x7FFF)
+}
+
+fn bytes(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+/// Little endian words
+fn words(hex: &str) -> Option<Vec<u16>> {
+    let bytes = bytes(hex)?;
+    if !bytes.len().is_multiple_of(2) {
+        return None;
+    }
+    Some(
+        bytes
+            .chunks(2)
+            .map(|pair| u16::from_le_bytes([pair[0], pair[1]]))
+            .collect(),
+    )
+}
+
+/// Little endian, like the `g` and `p` packets
+fn hex_word(word: u16) -> String {
+    let [low, high] = word.to_le_bytes();
+    format!("{low:02x}{high:02x}")
+}
@@ -0,0 +1,290 @@
+use crate::computer::{SCREEN_HEIGHT, SCREEN_WIDTH};
+
+use std::{fs, io::BufReader, path::Path};
+
+/// Black and white picture, like the Hack screen or a reference image.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Bitmap {
+    pub width: usize,
+    pub height: usize,
+    /// Row by row, `true` is black
+    pub pixels: Vec<bool>,
+}
+
+/// Result of comparing a bitmap with a reference image of the same size.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Difference {
+    /// Number of pixels that differ
+    pub count: usize,
+    /// Smallest rectangle containing every differing pixel as `(left, top,
+    /// right, bottom)`, inclusive. `None` if the images are equal.
+    pub bounds: Option<(usize, usize, usize, usize)>,
+    /// Image where exactly the differing pixels are black
+    pub image: Bitmap,
+}
+
+impl Bitmap {
+    /// All white bitmap
+    pub fn new(width: usize, height: usize) -> Self {
+        Bitmap {
+            width,
+            height,
+            pixels: vec![false; width * height],
+        }
+    }
+
+    /// The 512x256 screen from its memory map. The lowest bit of a word is
+    /// the leftmost of its 16 pixels.
+    pub fn from_screen(screen: &[u16]) -> Self {
+        let mut bitmap = Bitmap::new(SCREEN_WIDTH, SCREEN_HEIGHT);
+        for (i, pixel) in bitmap.pixels.iter_mut().enumerate() {
+            *pixel = screen[i / 16] & 1 << (i % 16) != 0;
+        }
+        bitmap
+    }
+
+    pub fn get(&self, x: usize, y: usize) -> bool {
+        self.pixels[y * self.width + x]
+    }
+
+    /// Reads a `*.pbm`, `*.png` or `*.gif` file. Colors darker than mid-gray
+    /// count as black, transparent pixels as white.
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let error = |e: &dyn std::fmt::Display| format!("couldn't read `{}`: {e}", path.display());
+        let bitmap = match extension(path).as_deref() {
+            Some("pbm") => Bitmap::from_pbm(&fs::read(path).map_err(|e| error(&e))?),
+            Some("png") => decode_png(path),
+            Some("gif") => decode_gif(path),
+            _ => return Err(error(&"expected a `.pbm`, `.png` or `.gif` file")),
+        };
+        bitmap.map_err(|e| error(&e))
+    }
+
+    /// Writes a `*.pbm` or `*.png` file, depending on the extension
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        let data = match extension(path).as_deref() {
+            Some("pbm") => self.to_pbm(),
+            Some("png") => self.to_png(),
+            _ => {
+                return Err(format!(
+                    "couldn't write `{}`: expected a `.pbm` or `.png` file",
+                    path.display()
+                ))
+            }
+        };
+        fs::write(path, data).map_err(|e| format!("couldn't write `{}`: {e}", path.display()))
+    }
+
+    /// Parses a plain (`P1`) or binary (`P4`) PBM, where 1 is black
+    pub fn from_pbm(data: &[u8]) -> Result<Self, String> {
+        let bad = || "malformed PBM file".to_owned();
+        let mut rest = data;
+        // Magic number, width and height, separated by whitespace and comments
+        let mut header = Vec::new();
+        while header.len() < 3 {
+            match rest.first() {
+                Some(b'#') => {
+                    let end = rest.iter().position(|b| *b == b'\n').unwrap_or(rest.len());
+                    rest = &rest[end..];
+                }
+                Some(b) if b.is_ascii_whitespace() => rest = &rest[1..],
+                Some(_) => {
+                    let end = rest
+                        .iter()
+                        .position(|b| b.is_ascii_whitespace() || *b == b'#')
+                        .unwrap_or(rest.len());
+                    header.push(String::from_utf8_lossy(&rest[..end]).into_owned());
+                    rest = &rest[end..];
+                }
+                None => return Err(bad()),
+            }
+        }
+        let width: usize = header[1].parse().map_err(|_| bad())?;
+        let height: usize = header[2].parse().map_err(|_| bad())?;
+        let mut bitmap = Bitmap::new(width, height);
+        match header[0].as_str() {
+            "P1" => {
+                let bits: Vec<bool> = rest
+                    .iter()
+                    .filter(|b| matches!(b, b'0' | b'1'))
+                    .map(|b| *b == b'1')
+                    .collect();
+                if bits.len() < width * height {
+                    return Err(bad());
+                }
+                bitmap.pixels.copy_from_slice(&bits[..width * height]);
+            }
+            "P4" => {
+                // A single whitespace character separates the header from the data
+                let data = rest.get(1..).ok_or_else(bad)?;
+                let row_bytes = width.div_ceil(8);
+                if data.len() < row_bytes * height {
+                    return Err(bad());
+                }
+                for (i, pixel) in bitmap.pixels.iter_mut().enumerate() {
+                    let (x, y) = (i % width, i / width);
+                    *pixel = data[y * row_bytes + x / 8] & 0x80 >> (x % 8) != 0;
+                }
+            }
+            _ => return Err("expected a `P1` or `P4` PBM file".to_owned()),
+        }
+        Ok(bitmap)
+    }
+
+    /// Binary PBM (`P4`), rows are padded to whole bytes
+    pub fn to_pbm(&self) -> Vec<u8> {
+        let mut data = format!("P4\n{} {}\n", self.width, self.height).into_bytes();
+        data.extend(self.packed_rows(true));
+        data
+    }
+
+    /// One bit grayscale PNG
+    pub fn to_png(&self) -> Vec<u8> {
+        let mut data = Vec::new();
+        let mut encoder = png::Encoder::new(&mut data, self.width as u32, self.height as u32);
+        encoder.set_color(png::ColorType::Grayscale);
+        encoder.set_depth(png::BitDepth::One);
+        // Writing into a vector only fails for invalid sizes
+        let mut writer = encoder.write_header().expect("Valid PNG header");
+        writer
+            .write_image_data(&self.packed_rows(false))
+            .expect("Data fits the header");
+        writer.finish().expect("Complete PNG");
+        data
+    }
+
+    /// Rows packed into bytes, most significant bit first. Black pixels are
+    /// set bits if `black_is_one`, otherwise black pixels are clear and the
+    /// padding bits are set, like PNG grayscale where 0 is black.
+    fn packed_rows(&self, black_is_one: bool) -> Vec<u8> {
+        let mut data = Vec::new();
+        for row in self.pixels.chunks(self.width) {
+            data.extend(row.chunks(8).map(|bits| {
+                let byte = bits
+                    .iter()
+                    .enumerate()
+                    .fold(0, |byte, (i, black)| byte | u8::from(*black) << (7 - i));
+                if black_is_one {
+                    byte
+                } else {
+                    !byte
+                }
+            }));
+        }
+        data
+    }
+
+    /// Resized to `width`x`height`, a pixel is black if most of the area it
+    /// covers in this bitmap is black. For reference images that are scaled
+    /// screenshots, like the ones of project 12.
+    pub fn scaled(&self, width: usize, height: usize) -> Bitmap {
+        let mut bitmap = Bitmap::new(width, height);
+        // Source pixels covered by the target pixel `i` of `n`
+        let span = |i: usize, n: usize, source: usize| {
+            let start = i * source / n;
+            start..((i + 1) * source).div_ceil(n).max(start + 1)
+        };
+        for y in 0..height {
+            for x in 0..width {
+                let (columns, rows) = (span(x, width, self.width), span(y, height, self.height));
+                let area = columns.len() * rows.len();
+                let black = rows
+                    .flat_map(|row| columns.clone().map(move |column| (column, row)))
+                    .filter(|(column, row)| self.get(*column, *row))
+                    .count();
+                bitmap.pixels[y * width + x] = 2 * black > area;
+            }
+        }
+        bitmap
+    }
+
+    /// Compares pixel by pixel, both images must have the same size
+    pub fn difference(&self, reference: &Bitmap) -> Result<Difference, String> {
+        if (self.width, self.height) != (reference.width, reference.height) {
+            return Err(format!(
+                "image is {}x{}, but the reference is {}x{}",
+                self.width, self.height, reference.width, reference.height
+            ));
+        }
+        let mut image = Bitmap::new(self.width, self.height);
+        let mut bounds: Option<(usize, usize, usize, usize)> = None;
+        for (i, (a, b)) in self.pixels.iter().zip(&reference.pixels).enumerate() {
+            if a == b {
+                continue;
+            }
+            image.pixels[i] = true;
+            let (x, y) = (i % self.width, i / self.width);
+            bounds = Some(match bounds {
+                Some((left, top, right, bottom)) => {
+                    (left.min(x), top.min(y), right.max(x), bottom.max(y))
+                }
+                None => (x, y, x, y),
+            });
+        }
+        Ok(Difference {
+            count: image.pixels.iter().filter(|p| **p).count(),
+            bounds,
+            image,
+        })
+    }
+}
+
+fn extension(path: &Path) -> Option<String> {
+    Some(path.extension()?.to_str()?.to_ascii_lowercase())
+}
+
+fn decode_png(path: &Path) -> Result<Bitmap, String> {
+    let file = fs::File::open(path).map_err(|e| e.to_string())?;
+    let mut decoder = png::Decoder::new(BufReader::new(file));
+    decoder.set_transformations(png::Transformations::normalize_to_color8());
+    let mut reader = decoder.read_info().map_err(|e| e.to_string())?;
+    let mut data = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut data).map_err(|e| e.to_string())?;
+    let channels = info.color_type.samples();
+    let mut bitmap = Bitmap::new(info.width as usize, info.height as usize);
+    for (y, row) in data.chunks(info.line_size).take(bitmap.height).enumerate() {
+        for (x, color) in row.chunks(channels).take(bitmap.width).enumerate() {
+            let (rgb, alpha) = match *color {
+                [gray] => ([gray; 3], 255),
+                [gray, alpha] => ([gray; 3], alpha),
+                [r, g, b] => ([r, g, b], 255),
+                [r, g, b, alpha] => ([r, g, b], alpha),
+                _ => unreachable!("PNGs have one to four channels"),
+            };
+            bitmap.pixels[y * bitmap.width + x] = is_black(rgb, alpha);
+        }
+    }
+    Ok(bitmap)
+}
+
+/// First frame of a GIF, drawn onto a white canvas
+fn decode_gif(path: &Path) -> Result<Bitmap, String> {
+    let file = fs::File::open(path).map_err(|e| e.to_string())?;
+    let mut options = gif::DecodeOptions::new();
+    options.set_color_output(gif::ColorOutput::RGBA);
+    let mut decoder = options
+        .read_info(BufReader::new(file))
+        .map_err(|e| e.to_string())?;
+    let mut bitmap = Bitmap::new(usize::from(decoder.width()), usize::from(decoder.height()));
+    let frame = decoder
+        .read_next_frame()
+        .map_err(|e| e.to_string())?
+        .ok_or("GIF has no frames")?;
+    let (left, top) = (usize::from(frame.left), usize::from(frame.top));
+    for (i, color) in frame.buffer.chunks(4).enumerate() {
+        let (x, y) = (
+            left + i % usize::from(frame.width),
+            top + i / usize::from(frame.width),
+        );
+        if x < bitmap.width && y < bitmap.height {
+            bitmap.pixels[y * bitmap.width + x] =
+                is_black([color[0], color[1], color[2]], color[3]);
+        }
+    }
+    Ok(bitmap)
+}
+
+fn is_black([r, g, b]: [u8; 3], alpha: u8) -> bool {
+    let luminance = (299 * u32::from(r) + 587 * u32::from(g) + 114 * u32::from(b)) / 1000;
+    alpha >= 128 && luminance < 128
+}
@@ -0,0 +1,184 @@
+use crate::{
+    computer::{Computer, Stop},
+    error::{ScriptError, ScriptErrorKind},
+    script::parse_value,
+};
+
+use std::{fs, path::Path};
+
+/// Codes of the keys without a printable character, as the Hack keyboard
+/// reports them. Printable characters have their ASCII code.
+pub const KEY_NAMES: [(&str, u16); 27] = [
+    ("space", 32),
+    ("newline", 128),
+    ("enter", 128),
+    ("backspace", 129),
+    ("left", 130),
+    ("up", 131),
+    ("right", 132),
+    ("down", 133),
+    ("home", 134),
+    ("end", 135),
+    ("pageup", 136),
+    ("pagedown", 137),
+    ("insert", 138),
+    ("delete", 139),
+    ("esc", 140),
+    ("f1", 141),
+    ("f2", 142),
+    ("f3", 143),
+    ("f4", 144),
+    ("f5", 145),
+    ("f6", 146),
+    ("f7", 147),
+    ("f8", 148),
+    ("f9", 149),
+    ("f10", 150),
+    ("f11", 151),
+    ("f12", 152),
+];
+
+/// Key presses and releases at given cycles, read from a file like
+///
+/// ```text
+/// // Move the paddle left for a while, then quit
+/// 100000 press left
+/// 400000 release
+/// 500000 press q
+/// 500100 press %D140
+/// ```
+///
+/// Keys are a single printable character, a name like `enter` or `f1`, or a
+/// code written like the values of test scripts. Pressing a key releases the
+/// previous one, like the Hack keyboard, which only reports one key.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Timeline {
+    /// Sorted by cycle, the key code is 0 for releases
+    events: Vec<(u64, u16)>,
+}
+
+/// Hack code of a key, see [`Timeline`] for how keys are written
+pub fn key_code(key: &str) -> Option<u16> {
+    let mut chars = key.chars();
+    if let (Some(c), None) = (chars.next(), chars.next()) {
+        return (' '..='~').contains(&c).then_some(c as u16);
+    }
+    if key.starts_with('%') {
+        return parse_value(key).ok();
+    }
+    let key = key.to_ascii_lowercase();
+    KEY_NAMES
+        .iter()
+        .find(|(name, _)| *name == key)
+        .map(|(_, code)| *code)
+}
+
+impl Timeline {
+    /// Reads a timeline file, reporting every bad line
+    pub fn load(path: &Path) -> Result<Self, Vec<ScriptError>> {
+        let error = |line, kind| ScriptError {
+            file: path.to_owned(),
+            line,
+            kind,
+        };
+        let source = fs::read_to_string(path).map_err(|e| {
+            vec![error(
+                None,
+                ScriptErrorKind::Io(format!("couldn't read file: {e}")),
+            )]
+        })?;
+        Timeline::parse(&source).map_err(|errors| {
+            errors
+                .into_iter()
+                .map(|(line, kind)| error(Some(line), kind))
+                .collect()
+        })
+    }
+
+    /// Errors come with the line they were found on
+    pub fn parse(source: &str) -> Result<Self, Vec<(usize, ScriptErrorKind)>> {
+        let mut events = Vec::new();
+        let mut errors = Vec::new();
+        for (i, line) in source.lines().enumerate() {
+            let text = line.split("//").next().unwrap_or_default();
+            let words: Vec<&str> = text.split_whitespace().collect();
+            let event = match words[..] {
+                [] => continue,
+                [cycle, "press", key] => cycle_of(cycle).and_then(|cycle| {
+                    let code = key_code(key)
+                        .filter(|code| *code != 0)
+                        .ok_or_else(|| ScriptErrorKind::BadValue(key.to_owned()))?;
+                    Ok((cycle, code))
+                }),
+                [cycle, "release"] => cycle_of(cycle).map(|cycle| (cycle, 0)),
+                _ => Err(ScriptErrorKind::Syntax(
+                    "expected `CYCLE press KEY` or `CYCLE release`".to_owned(),
+                )),
+            };
+            match event {
+                Ok(event) => events.push(event),
+                Err(kind) => errors.push((i + 1, kind)),
+            }
+        }
+        if !errors.is_empty() {
+            return Err(errors);
+        }
+        // Events of the same cycle stay in file order, the last one wins
+        Ok(Timeline::from_events(events))
+    }
+
+    /// Timeline of `(cycle, key)` events in any order, 0 releases the key
+    pub(crate) fn from_events(mut events: Vec<(u64, u16)>) -> Self {
+        events.sort_by_key(|(cycle, _)| *cycle);
+        Timeline { events }
+    }
+
+    /// Key pressed at `cycle`, `None` before the first event
+    pub fn key_at(&self, cycle: u64) -> Option<u16> {
+        let after = self.events.partition_point(|(c, _)| *c <= cycle);
+        after.checked_sub(1).map(|i| self.events[i].1)
+    }
+
+    /// Cycle of the first event after `cycle`
+    fn next_event(&self, cycle: u64) -> Option<u64> {
+        let after = self.events.partition_point(|(c, _)| *c <= cycle);
+        self.events.get(after).map(|(c, _)| *c)
+    }
+}
+
+fn cycle_of(text: &str) -> Result<u64, ScriptErrorKind> {
+    text.parse()
+        .map_err(|_| ScriptErrorKind::BadValue(text.to_owned()))
+}
+
+impl Computer {
+    /// Like [`Computer::run`], but sets the keyboard register to the key the
+    /// timeline has pressed at each cycle. The cycle counts from the start,
+    /// so runs can be split into several calls.
+    pub fn run_with_keys(&mut self, max_cycles: u64, timeline: &Timeline) -> Stop {
+        self.run_split(max_cycles, timeline, Computer::run)
+    }
+
+    /// Calls `run` with the number of cycles up to each event of the
+    /// timeline, setting the key in between
+    pub(crate) fn run_split(
+        &mut self,
+        max_cycles: u64,
+        timeline: &Timeline,
+        mut run: impl FnMut(&mut Computer, u64) -> Stop,
+    ) -> Stop {
+        let end = self.cycles + max_cycles;
+        loop {
+            if let Some(key) = timeline.key_at(self.cycles) {
+                self.set_key(key);
+            }
+            let until = timeline
+                .next_event(self.cycles)
+                .map_or(end, |cycle| cycle.min(end));
+            let stop = run(self, until - self.cycles);
+            if stop == Stop::Halted || self.cycles == end {
+                return stop;
+            }
+        }
+    }
+}
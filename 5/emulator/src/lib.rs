@@ -0,0 +1,734 @@
+//! Emulator for the Hack computer.
+//!
+//! A [`Computer`] is loaded with the words of a `*.hack` file, or of an
+//! `*.asm` file assembled on the fly, and executes them one instruction per
+//! cycle. RAM, ROM and registers can be inspected and changed in between.
+//! The [`Engine`] runs the same programs much faster on pre-decoded blocks.
+//!
+//! [`run_script`] runs the `*.tst` test scripts of the course on it, and on
+//! the [`VmEmulator`] for scripts stepping through VM code.
+//!
+//! The screen can be saved as a [`Bitmap`] and compared with reference
+//! images, keyboard input can be scripted with a [`Timeline`]. The
+//! [`Debugger`] stops programs at breakpoints and watched RAM words, the
+//! [`Profiler`] counts the cycles spent in each function and
+//! [`run_in_terminal`] shows the screen as text while the program runs. A
+//! [`Trace`] records the last instructions to undo, export and [`replay`]
+//! them, and [`serve_gdb`] lets GDB clients debug programs.
+
+mod computer;
+mod debugger;
+mod engine;
+mod error;
+mod gdb;
+mod image;
+mod keyboard;
+mod profile;
+mod runner;
+mod script;
+mod terminal;
+mod trace;
+mod vm;
+
+pub use computer::{alu, Computer, Stop, KBD, RAM_SIZE, SCREEN, SCREEN_HEIGHT, SCREEN_WIDTH};
+pub use debugger::{Debugger, Event};
+pub use engine::Engine;
+pub use error::{ScriptError, ScriptErrorKind};
+pub use gdb::{serve_gdb, Connection, REGISTERS};
+pub use image::{Bitmap, Difference};
+pub use keyboard::{key_code, Timeline, KEY_NAMES};
+pub use profile::{FunctionProfile, Profiler};
+pub use runner::run_script;
+pub use script::{parse_script, Column, Command, Comparison, Condition, Format, Radix, Statement};
+pub use terminal::{hack_key, render, run_in_terminal, Charset};
+pub use trace::{replay, Trace};
+pub use vm::VmEmulator;
+
+use assembler::{
+    assemble, assemble_program, parse_hack, read_source, AsmError, ErrorKind, SymbolTable, ROM_SIZE,
+};
+
+use std::path::Path;
+
+/// Machine words of a `*.hack` file, `*.asm` files are assembled
+pub fn load_program(file: &Path) -> Result<Vec<u16>, Vec<AsmError>> {
+    let words = if file.extension().is_some_and(|e| e == "asm") {
+        assemble(file)?
+    } else {
+        parse_hack(file, &read_source(file)?)?
+    };
+    if words.len() > ROM_SIZE {
+        return Err(vec![AsmError {
+            file: file.to_owned(),
+            location: None,
+            kind: ErrorKind::RomOverflow(words.len()),
+        }]);
+    }
+    Ok(words)
+}
+
+/// Machine words with the symbols of an `*.asm` file, or with only the
+/// predefined symbols for a `*.hack` file
+pub fn load_with_symbols(file: &Path) -> Result<(Vec<u16>, SymbolTable), Vec<AsmError>> {
+    if file.extension().is_some_and(|e| e == "asm") {
+        let program = assemble_program(file, &read_source(file)?, SymbolTable::predefined())?;
+        Ok((program.words, program.symbols))
+    } else {
+        Ok((load_program(file)?, SymbolTable::predefined()))
+    }
+}
+
+impl Computer {
+    /// Computer running the `*.hack` or `*.asm` file
+    pub fn load(file: &Path) -> Result<Self, Vec<AsmError>> {
+        Ok(Computer::new(&load_program(file)?))
+    }
+
+    /// The current content of the screen
+    pub fn screenshot(&self) -> Bitmap {
+        Bitmap::from_screen(self.screen())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use assembler::{assemble_program, assemble_source, Comp, SymbolTable};
+
+    #[test]
+    fn add() {
+        let mut computer = Computer::load(Path::new("../Add.hack")).unwrap();
+        // Add.hack has no halt loop, it runs into the zeroed rest of the ROM
+        assert_eq!(computer.run(6), Stop::CycleLimit);
+        assert_eq!(computer.ram[0], 5);
+    }
+
+    #[test]
+    fn max() {
+        let mut computer = Computer::load(Path::new("../../6/max/Max.asm")).unwrap();
+        for (r0, r1) in [(3, 5), (23456, 12345), (0xFFFF, 2)] {
+            computer.reset();
+            computer.ram[0] = r0;
+            computer.ram[1] = r1;
+            assert_eq!(computer.run(100), Stop::Halted);
+            assert_eq!(computer.ram[2], (r0 as i16).max(r1 as i16) as u16);
+        }
+    }
+
+    #[test]
+    fn rect() {
+        let mut computer = Computer::load(Path::new("../Rect.hack")).unwrap();
+        computer.ram[0] = 4;
+        assert_eq!(computer.run(1000), Stop::Halted);
+        for y in 0..5 {
+            for x in 0..17 {
+                assert_eq!(computer.pixel(x, y), x < 16 && y < 4, "pixel {x}, {y}");
+            }
+        }
+    }
+
+    #[test]
+    fn cycle_limit() {
+        let program = assemble_source(Path::new("in.asm"), "(LOOP)\n@KBD\nD=M\n@LOOP\nD;JEQ\n");
+        let mut computer = Computer::new(&program.unwrap());
+        assert_eq!(computer.run(10), Stop::CycleLimit);
+        assert_eq!(computer.cycles, 10);
+        computer.set_key(65);
+        computer.run(6);
+        assert_eq!((computer.pc, computer.d), (4, 65));
+    }
+
+    #[test]
+    fn memory_and_jump_use_previous_a() {
+        let source = "@100\nD=A\n@7\nAM=D+A\n@5\nA=A+1;JMP\n(SIX)\n(END)\n@END\n0;JMP\n@KBD\nM=1\n";
+        let mut computer = Computer::new(&assemble_source(Path::new("in.asm"), source).unwrap());
+        computer.run(6);
+        // `AM=D+A` stores at the old A, `A=A+1;JMP` jumps to the old A
+        assert_eq!((computer.ram[7], computer.a, computer.pc), (107, 6, 5));
+        assert_eq!(computer.run(10), Stop::Halted);
+        computer.pc = 10;
+        computer.run(2);
+        assert_eq!(computer.key(), 0);
+    }
+
+    #[test]
+    fn alu_computes_every_mnemonic() {
+        let (x, y): (u16, u16) = (0x1234, 0x00FF);
+        let expected = [
+            (Comp::Zero, 0),
+            (Comp::One, 1),
+            (Comp::MinusOne, 0xFFFF),
+            (Comp::D, x),
+            (Comp::A, y),
+            (Comp::NotD, !x),
+            (Comp::NotA, !y),
+            (Comp::NegD, x.wrapping_neg()),
+            (Comp::NegA, y.wrapping_neg()),
+            (Comp::DPlusOne, x + 1),
+            (Comp::APlusOne, y + 1),
+            (Comp::DMinusOne, x - 1),
+            (Comp::AMinusOne, y - 1),
+            (Comp::DPlusA, x + y),
+            (Comp::DMinusA, x - y),
+            (Comp::AMinusD, y.wrapping_sub(x)),
+            (Comp::DAndA, x & y),
+            (Comp::DOrA, x | y),
+        ];
+        for (comp, value) in expected {
+            assert_eq!(alu(x, y, u16::from(comp) & 0b11_1111), value, "{comp}");
+        }
+    }
+
+    #[test]
+    fn scripts() {
+        for script in [
+            "../../4/mult/Mult.tst",
+            "../ComputerMax.tst",
+            "../../7/StackArithmetic/StackTest/StackTestVME.tst",
+            "../../8/FunctionCalls/FibonacciElement/FibonacciElementVME.tst",
+        ] {
+            if let Err(e) = run_script(Path::new(script)) {
+                panic!("{e}");
+            }
+        }
+    }
+
+    #[test]
+    fn script_mismatch() {
+        let dir = std::env::temp_dir().join("emulator-script-mismatch");
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("Prog.asm"), "@7\nD=A\n@0\nM=D\n").unwrap();
+        std::fs::write(
+            dir.join("Prog.tst"),
+            "load Prog.asm, output-file Prog.out, compare-to Prog.cmp,\n\
+             output-list RAM[0]%D2.6.2 time%S1.4.1;\n\
+             repeat 4 {\n  ticktock;\n}\noutput;\n",
+        )
+        .unwrap();
+        std::fs::write(
+            dir.join("Prog.cmp"),
+            "|  RAM[0]  | time |\n|       8  | 4    |\n",
+        )
+        .unwrap();
+        let error = run_script(&dir.join("Prog.tst")).unwrap_err();
+        assert_eq!(error.file, dir.join("Prog.cmp"));
+        assert_eq!(error.line, Some(2));
+        assert_eq!(
+            error.to_string(),
+            format!(
+                "{}:2: error: comparison failure in column 1 `RAM[0]`, expected `       8  `, got `       7  `",
+                dir.join("Prog.cmp").display()
+            )
+        );
+        let output = std::fs::read_to_string(dir.join("Prog.out")).unwrap();
+        assert_eq!(output, "|  RAM[0]  | time |\n|       7  | 4    |\n");
+    }
+
+    #[test]
+    fn vm_operands_out_of_range() {
+        let load = |code: &str| VmEmulator::from_sources(&[("Main.vm".into(), code.into())]);
+        assert!(load("push static 65518\ncall Main.f 65530\nfunction Main.f 0\n").is_ok());
+        assert_eq!(
+            load("pop static 65519\n").unwrap_err(),
+            "Main.vm:1: static index out of range in `pop static 65519`"
+        );
+        assert_eq!(
+            load("function Main.f 0\ncall Main.f 65531\n").unwrap_err(),
+            "Main.vm:2: too many arguments in `call Main.f 65531`"
+        );
+    }
+
+    #[test]
+    fn formats() {
+        let column: Column = "RAM[16]%X1.4.1".parse().unwrap();
+        assert_eq!(column.format.header(&column.name), "RAM[16");
+        assert_eq!(column.format.number(0xBEEF), " BEEF ");
+        let column: Column = "out%B1.16.1".parse().unwrap();
+        assert_eq!(column.format.number(5), " 0000000000000101 ");
+        let column: Column = "x%D1.6.1".parse().unwrap();
+        assert_eq!(column.format.header(&column.name), "   x    ");
+        assert_eq!(column.format.number(0xFFFF), "     -1 ");
+    }
+
+    #[test]
+    fn screenshot_round_trips() {
+        let mut computer = Computer::load(Path::new("../Rect.hack")).unwrap();
+        computer.ram[0] = 4;
+        computer.ram[usize::from(KBD) - 1] = 0x8001;
+        computer.run(1000);
+        let screenshot = computer.screenshot();
+        assert!(screenshot.get(0, 0) && screenshot.get(15, 3) && !screenshot.get(16, 0));
+        assert!(screenshot.get(496, 255) && screenshot.get(511, 255));
+        let dir = std::env::temp_dir();
+        for file in ["emulator-screen.pbm", "emulator-screen.png"] {
+            screenshot.save(&dir.join(file)).unwrap();
+            assert_eq!(Bitmap::load(&dir.join(file)).unwrap(), screenshot, "{file}");
+        }
+    }
+
+    #[test]
+    fn odd_sized_images() {
+        let plain = Bitmap::from_pbm(b"P1\n# comment\n3 2\n1 0 1\n011\n").unwrap();
+        assert_eq!(plain.pixels, [true, false, true, false, true, true]);
+        // Rows of binary PBMs are padded to whole bytes
+        assert_eq!(plain.to_pbm(), b"P4\n3 2\n\xA0\x60");
+        assert_eq!(Bitmap::from_pbm(&plain.to_pbm()).unwrap(), plain);
+        let path = std::env::temp_dir().join("emulator-odd.png");
+        plain.save(&path).unwrap();
+        assert_eq!(Bitmap::load(&path).unwrap(), plain);
+        assert!(plain.save(Path::new("screen.gif")).is_err());
+    }
+
+    #[test]
+    fn screen_difference() {
+        let mut computer = Computer::new(&[]);
+        let reference = computer.screenshot();
+        computer.ram[usize::from(SCREEN) + 33] = 0b110;
+        computer.ram[usize::from(SCREEN) + 32 * 9] = 0x8000;
+        let difference = computer.screenshot().difference(&reference).unwrap();
+        assert_eq!(difference.count, 3);
+        assert_eq!(difference.bounds, Some((15, 1, 18, 9)));
+        assert!(difference.image.get(17, 1) && !difference.image.get(16, 1));
+        let equal = reference.difference(&reference).unwrap();
+        assert_eq!((equal.count, equal.bounds), (0, None));
+        assert!(reference.difference(&reference.scaled(256, 128)).is_err());
+    }
+
+    #[test]
+    fn scaled_reference_images() {
+        let reference =
+            Bitmap::load(Path::new("../../12/ScreenTest/ScreenTestOutput.gif")).unwrap();
+        assert_eq!((reference.width, reference.height), (366, 193));
+        // The ground line across the picture
+        assert!((0..366).filter(|x| reference.get(*x, 162)).count() > 300);
+        let mut bitmap = Bitmap::new(4, 4);
+        bitmap.pixels[..8].fill(true);
+        bitmap.pixels[8] = true;
+        assert_eq!(bitmap.scaled(2, 2).pixels, [true, true, false, false]);
+        assert_eq!(bitmap.scaled(4, 4), bitmap);
+    }
+
+    #[test]
+    fn key_codes() {
+        assert_eq!(key_code("a"), Some(97));
+        assert_eq!(key_code("7"), Some(55));
+        assert_eq!(key_code("Left"), Some(130));
+        assert_eq!(key_code("enter"), Some(128));
+        assert_eq!(key_code("F12"), Some(152));
+        assert_eq!(key_code("%D140"), Some(140));
+        assert_eq!(key_code("shift"), None);
+    }
+
+    #[test]
+    fn keyboard_timeline() {
+        // R0 is the highest key code seen, R1 the last one
+        let source = "(LOOP)\n@KBD\nD=M\n@R1\nM=D\n@R0\nD=D-M\n@LOOP\nD;JLE\n\
+                      @KBD\nD=M\n@R0\nM=D\n@LOOP\n0;JMP\n";
+        let mut computer = Computer::new(&assemble_source(Path::new("in.asm"), source).unwrap());
+        let timeline =
+            Timeline::parse("// Arrow keys\n20 press left\n\n80 release\n50 press up\n").unwrap();
+        assert_eq!(timeline.key_at(19), None);
+        assert_eq!(timeline.key_at(65), Some(131));
+        assert_eq!(computer.run_with_keys(36, &timeline), Stop::CycleLimit);
+        assert_eq!((computer.ram[0], computer.ram[1]), (130, 130));
+        computer.run_with_keys(34, &timeline);
+        assert_eq!((computer.ram[0], computer.ram[1]), (131, 131));
+        computer.run_with_keys(40, &timeline);
+        assert_eq!(
+            (computer.cycles, computer.ram[0], computer.ram[1]),
+            (110, 131, 0)
+        );
+    }
+
+    #[test]
+    fn bad_timeline_lines() {
+        let errors =
+            Timeline::parse("10 press\nsoon release\n20 press shift\n30 press %D0\n40 release\n")
+                .unwrap_err();
+        let lines: Vec<usize> = errors.iter().map(|(line, _)| *line).collect();
+        assert_eq!(lines, [1, 2, 3, 4]);
+        assert_eq!(errors[2].1.to_string(), "bad value `shift`");
+    }
+
+    fn debugger() -> Debugger {
+        let source = "@256\nD=A\n@SP\nM=D\n(Main.main)\n@SP\nM=M+1\n@count\nM=M+1\nD=M\n\
+                      @5\nD=D-A\n@Main.main\nD;JLT\n(END)\n@END\n0;JMP\n";
+        let program =
+            assemble_program(Path::new("in.asm"), source, SymbolTable::predefined()).unwrap();
+        Debugger::new(Computer::new(&program.words), program.symbols)
+    }
+
+    #[test]
+    fn breakpoints_and_watchpoints() {
+        let mut debugger = debugger();
+        let main = debugger.rom_address("Main.main").unwrap();
+        assert_eq!(
+            debugger.rom_address("40000"),
+            Err("address 40000 is outside of the ROM".to_owned())
+        );
+        debugger.add_breakpoint(main);
+        assert_eq!(debugger.resume(), Event::Breakpoint(4));
+        // Continuing steps over the breakpoint it stopped at
+        assert_eq!(debugger.step(1), Event::Stepped);
+        assert_eq!(debugger.resume(), Event::Breakpoint(4));
+        assert_eq!(debugger.computer.ram[16], 1);
+        debugger.remove_breakpoint(main);
+        debugger.add_watchpoint(debugger.ram_address("RAM[SP]").unwrap());
+        let expected = Event::Watchpoint {
+            address: 0,
+            old: 257,
+            new: 258,
+        };
+        assert_eq!(debugger.resume(), expected);
+        assert_eq!(debugger.computer.pc, 6);
+        debugger.remove_watchpoint(0);
+        assert_eq!(debugger.resume(), Event::Halted);
+        assert_eq!(debugger.computer.ram[16], 5);
+        assert_eq!(debugger.location(10), "Main.main+6");
+        assert_eq!(debugger.location(2), "2");
+    }
+
+    #[test]
+    fn debugger_commands() {
+        let mut debugger = debugger();
+        assert_eq!(
+            debugger.execute("b Main.main").unwrap(),
+            "breakpoint at 4 (Main.main)\n"
+        );
+        assert_eq!(
+            debugger.execute("c").unwrap(),
+            "breakpoint at 4\n4 (Main.main): @0\n"
+        );
+        assert_eq!(
+            debugger.execute("registers").unwrap(),
+            "A  =      0  D =    256  PC = 4 (Main.main)  cycles = 4\n\
+             SP = 256  LCL = 0  ARG = 0  THIS = 0  THAT = 0\n"
+        );
+        assert_eq!(
+            debugger.execute("step 2").unwrap(),
+            "6 (Main.main+2): @16\n"
+        );
+        assert_eq!(debugger.execute("set count=-3").unwrap(), "");
+        assert_eq!(
+            debugger.execute("p count 2").unwrap(),
+            "RAM[16] = -3\nRAM[17] = 0\n"
+        );
+        let listing = debugger.execute("list 12").unwrap();
+        assert!(
+            listing.contains("\n      11  @4          // Main.main\n"),
+            "{listing}"
+        );
+        assert!(listing.contains("(END)\n      13  @13"), "{listing}");
+        assert!(debugger
+            .execute("list")
+            .unwrap()
+            .contains("  *    4  @0\n       5  M=M+1\n=>     6  @16\n"));
+        assert_eq!(
+            debugger.execute("watch Main.main").unwrap_err(),
+            "unknown symbol `Main.main`"
+        );
+        assert!(debugger.execute("delete 5").is_err());
+        assert!(debugger.execute("jump 5").is_err());
+    }
+
+    #[test]
+    fn profile() {
+        // Calls like translated VM code, returning to the `$ret.` labels
+        let source = "@Sys.init$ret.0\nD=A\n@R15\nM=D\n@Sys.init\n0;JMP\n(Sys.init$ret.0)\n\
+                      (END)\n@END\n0;JMP\n\
+                      (Sys.init)\n@Sys.init$ret.1\nD=A\n@R14\nM=D\n@Main.f\n0;JMP\n\
+                      (Sys.init$ret.1)\n@R15\nA=M\n0;JMP\n\
+                      (Main.f)\n@R0\nM=M+1\n@R14\nA=M\n0;JMP\n";
+        let program =
+            assemble_program(Path::new("in.asm"), source, SymbolTable::predefined()).unwrap();
+        let mut computer = Computer::new(&program.words);
+        let mut profiler = Profiler::new(&program.symbols);
+        let stop = profiler.run(&mut computer, 100, &Timeline::default());
+        assert_eq!(
+            (stop, computer.cycles, computer.ram[0]),
+            (Stop::Halted, 20, 1)
+        );
+        assert_eq!((profiler.count(17), profiler.count(6)), (1, 0));
+        let summary: Vec<_> = profiler
+            .functions()
+            .into_iter()
+            .map(|p| (p.name, p.self_cycles, p.inclusive_cycles, p.calls))
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                ("Sys.init".to_owned(), 9, 14, 1),
+                ("(start)".to_owned(), 6, 20, 0),
+                ("Main.f".to_owned(), 5, 5, 1),
+            ]
+        );
+        assert_eq!(
+            profiler.folded(),
+            "(start) 6\n(start);Sys.init 9\n(start);Sys.init;Main.f 5\n"
+        );
+        let table = profiler.table();
+        assert!(
+            table.contains("         9   45.0%          14   70.0%         1  Sys.init\n"),
+            "{table}"
+        );
+        assert!(table.ends_with("        20  100.0%                                cycles total\n"));
+    }
+
+    #[test]
+    fn text_rendering() {
+        let mut bitmap = Bitmap::new(3, 5);
+        for (x, y) in [(0, 0), (1, 1), (0, 3), (2, 4)] {
+            bitmap.pixels[y * 3 + x] = true;
+        }
+        assert_eq!(
+            render(&bitmap, Charset::Braille),
+            ["\u{2851}\u{2800}", "\u{2800}\u{2801}"]
+        );
+        assert_eq!(render(&bitmap, Charset::HalfBlock), ["▀▄ ", "▄  ", "  ▀"]);
+        let screen = render(&Computer::new(&[]).screenshot(), Charset::Braille);
+        assert_eq!((screen.len(), screen[0].chars().count()), (64, 256));
+    }
+
+    #[test]
+    fn terminal_keys() {
+        use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+        let key = |code| hack_key(&KeyEvent::new(code, KeyModifiers::NONE));
+        assert_eq!(key(KeyCode::Char('x')), Some(120));
+        assert_eq!(key(KeyCode::Left), Some(130));
+        assert_eq!(key(KeyCode::Esc), Some(140));
+        assert_eq!(key(KeyCode::F(12)), Some(152));
+        assert_eq!(key(KeyCode::Tab), None);
+    }
+
+    /// Runs both engines in slices of `slices` cycles and compares the
+    /// complete state after each one
+    fn assert_same_as_reference(program: &[u16], slices: &[u64], keys: &Timeline) {
+        let mut reference = Computer::new(program);
+        let mut fast = reference.clone();
+        let mut engine = Engine::new(&fast.rom);
+        for cycles in slices {
+            let expected = reference.run_with_keys(*cycles, keys);
+            let stop = engine.run_with_keys(&mut fast, *cycles, keys);
+            assert_eq!(stop, expected);
+            assert_eq!(
+                (fast.a, fast.d, fast.pc, fast.cycles),
+                (reference.a, reference.d, reference.pc, reference.cycles)
+            );
+            assert!(
+                fast.ram == reference.ram,
+                "RAM differs after {} cycles",
+                fast.cycles
+            );
+        }
+    }
+
+    #[test]
+    fn engine_runs_pong_like_the_reference() {
+        let program = load_program(Path::new("../../6/pong/Pong.hack")).unwrap();
+        let keys =
+            Timeline::parse("50000 press left\n300000 release\n600000 press right\n").unwrap();
+        assert_same_as_reference(&program, &[1, 63, 200_000, 7, 1_000_000], &keys);
+    }
+
+    #[test]
+    fn engine_runs_random_programs_like_the_reference() {
+        let mut seed: u32 = 1;
+        let mut random = || {
+            seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12345);
+            (seed >> 16) as u16
+        };
+        for _ in 0..20 {
+            // Mostly C-instructions, with A-instructions into the program
+            let program: Vec<u16> = (0..200)
+                .map(|_| match random() % 4 {
+                    0 => random() % 220,
+                    1 => 0x8000 | random(),
+                    _ => 0xE000 | random(),
+                })
+                .collect();
+            assert_same_as_reference(&program, &[100, 5_000, 3, 20_000], &Timeline::default());
+        }
+    }
+
+    #[test]
+    fn engine_stops_at_halt_loops() {
+        let mut computer = Computer::load(Path::new("../../6/max/Max.asm")).unwrap();
+        computer.ram[..2].copy_from_slice(&[3, 9]);
+        let mut engine = Engine::new(&computer.rom);
+        assert_eq!(engine.run(&mut computer, 1000), Stop::Halted);
+        assert_eq!((computer.ram[2], computer.cycles), (9, 12));
+        assert_eq!(engine.run(&mut computer, 1000), Stop::Halted);
+        assert_eq!(computer.cycles, 12);
+    }
+
+    /// Registers, cycles and RAM, to compare states
+    fn state(computer: &Computer) -> (u16, u16, u16, u64, Vec<u16>) {
+        let c = computer;
+        (c.a, c.d, c.pc, c.cycles, c.ram.to_vec())
+    }
+
+    #[test]
+    fn trace_steps_back() {
+        let program = load_program(Path::new("../../6/pong/Pong.hack")).unwrap();
+        let mut computer = Computer::new(&program);
+        let keys = Timeline::parse("1000 press left\n1500 release\n1800 press x\n").unwrap();
+        let mut trace = Trace::new(&computer, 5000);
+        let mut states = Vec::new();
+        for cycles in [900, 600, 450] {
+            states.push(state(&computer));
+            assert_eq!(trace.run(&mut computer, cycles, &keys), Stop::CycleLimit);
+        }
+        assert_eq!((trace.len(), trace.start()), (1950, 0));
+        for (expected, cycles) in states.iter().zip([900, 600, 450]).rev() {
+            for _ in 0..cycles {
+                assert!(trace.step_back(&mut computer));
+            }
+            assert!(state(&computer) == *expected);
+        }
+        assert!(!trace.step_back(&mut computer));
+        // Stepping forward again presses the keys of the recording
+        for _ in 0..1950 {
+            trace.step(&mut computer);
+        }
+        let mut expected = Computer::new(&program);
+        expected.run_with_keys(1950, &keys);
+        assert!(state(&computer) == state(&expected));
+
+        // Only the last instructions are kept
+        let mut trace = Trace::new(&computer, 100);
+        trace.run(&mut computer, 1000, &keys);
+        assert_eq!((trace.len(), trace.start()), (100, 2850));
+    }
+
+    #[test]
+    fn trace_replay() {
+        let program = load_program(Path::new("../../6/pong/Pong.hack")).unwrap();
+        let keys = Timeline::parse("20000 press left\n60000 release\n").unwrap();
+        let mut computer = Computer::new(&program);
+        let mut trace = Trace::new(&computer, 1000);
+        trace.run(&mut computer, 100_000, &keys);
+        let text = trace.to_text();
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines.len(), 1003);
+        assert_eq!(
+            lines[..3],
+            [
+                "// Hack trace of cycles 99000 to 99999",
+                "20000 key 130",
+                "60000 key 0"
+            ]
+        );
+        assert!(lines.iter().any(|line| line.contains(" RAM[")));
+
+        // Replays from the start, including the key presses before the trace
+        let mut replayed = Computer::new(&program);
+        let replay_trace = replay(&mut replayed, &text, 1000).unwrap();
+        assert!(state(&replayed) == state(&computer));
+        assert_eq!(replay_trace.to_text(), text);
+
+        // A different build of the program differs at some instruction
+        let mut patched = program.clone();
+        let pc: u16 = lines[500].split(' ').nth(1).unwrap().parse().unwrap();
+        patched[usize::from(pc)] = 0xEA88; // M=0
+        let error = replay(&mut Computer::new(&patched), &text, 10).unwrap_err();
+        assert!(error.starts_with("line "), "{error}");
+        assert!(error.contains("the replay executed"), "{error}");
+        assert!(replay(&mut Computer::new(&program), "5 x y z", 10).is_err());
+    }
+
+    #[test]
+    fn debugger_steps_back() {
+        let mut debugger = debugger();
+        debugger.execute("step 8").unwrap();
+        assert_eq!(debugger.computer.ram[16], 1);
+        debugger.execute("watch count").unwrap();
+        assert_eq!(
+            debugger.execute("back 2").unwrap(),
+            "6 (Main.main+2): @16\n"
+        );
+        assert_eq!(
+            (debugger.computer.ram[16], debugger.computer.cycles),
+            (0, 6)
+        );
+        // The watchpoint sees the undone value as the current one
+        assert_eq!(
+            debugger.execute("s 2").unwrap(),
+            "RAM[16] changed from 0 to 1\n8 (Main.main+4): D=M\n"
+        );
+        assert_eq!(
+            debugger.execute("back 20").unwrap(),
+            "history starts at cycle 0, 8 instructions undone\n0 (0): @256\n"
+        );
+    }
+
+    /// Sends a packet like a GDB client and returns the reply
+    fn rsp(stream: &mut std::net::TcpStream, data: &str) -> String {
+        use std::io::{Read, Write};
+
+        let sum = data.bytes().fold(0u8, |sum, byte| sum.wrapping_add(byte));
+        write!(stream, "${data}#{sum:02x}").unwrap();
+        let mut byte = [0];
+        stream.read_exact(&mut byte).unwrap();
+        assert_eq!(byte[0], b'+');
+        let mut reply = Vec::new();
+        stream.read_exact(&mut byte).unwrap();
+        assert_eq!(byte[0], b'$');
+        loop {
+            stream.read_exact(&mut byte).unwrap();
+            if byte[0] == b'#' {
+                break;
+            }
+            reply.push(byte[0]);
+        }
+        let mut checksum = [0; 2];
+        stream.read_exact(&mut checksum).unwrap();
+        let sum = reply.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte));
+        assert_eq!(checksum, format!("{sum:02x}").as_bytes());
+        stream.write_all(b"+").unwrap();
+        String::from_utf8(reply).unwrap()
+    }
+
+    #[test]
+    fn gdb_remote_protocol() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let mut debugger = debugger();
+        let server = std::thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            stream.set_nodelay(true).unwrap();
+            serve_gdb(&mut debugger, stream).unwrap();
+            debugger
+        });
+        let mut client = std::net::TcpStream::connect(address).unwrap();
+        client.set_nodelay(true).unwrap();
+        let mut rsp = |data| rsp(&mut client, data);
+        assert!(rsp("qSupported:swbreak+").contains("qXfer:features:read+"));
+        assert!(rsp("qXfer:features:read:target.xml:0,1000").contains(r#"<reg name="lcl""#));
+        assert_eq!(rsp("?"), "S05");
+
+        // Main.main is at 4
+        assert_eq!(rsp("Z0,4,2"), "OK");
+        assert_eq!(rsp("c"), "S05");
+        assert_eq!(rsp("g"), "00000001040000010000000000000000");
+        assert_eq!(rsp("s"), "S05");
+        assert_eq!(rsp("p2"), "0500");
+        assert_eq!(rsp("c"), "S05");
+        assert_eq!(rsp("p2"), "0400");
+        // RAM[0] and the count in RAM[16]
+        assert_eq!(rsp("m0,2"), "0101");
+        assert_eq!(rsp("m20,2"), "0100");
+        assert_eq!(rsp("M20,2:feff"), "OK");
+        assert_eq!(rsp("P1=0900"), "OK");
+        assert_eq!(rsp("z0,4,2"), "OK");
+        assert_eq!(rsp("c"), "S05");
+        assert_eq!(rsp("m20,2"), "0500");
+        assert_eq!(rsp("Z1,4,2"), "");
+        assert_eq!(rsp("m10000,2"), "E01");
+        assert_eq!(rsp("m2,ffffffffffffffff"), "E01");
+        assert_eq!(rsp("D"), "OK");
+
+        let debugger = server.join().unwrap();
+        assert_eq!(debugger.computer.d, 0);
+        assert_eq!(debugger.computer.ram[0], 264);
+    }
+}
@@ -0,0 +1,398 @@
+use emulator::{
+    load_with_symbols, run_in_terminal, run_script, serve_gdb, Bitmap, Charset, Computer, Debugger,
+    Engine, Profiler, Stop, Timeline, Trace, RAM_SIZE,
+};
+
+use std::{
+    env, fs,
+    io::{self, BufRead, Write},
+    net::TcpListener,
+    path::Path,
+    process,
+    str::FromStr,
+};
+
+const USAGE: &str = "Usage: emulator [--cycles N] [--set ADDRESS=VALUE]... \
+[--show ADDRESS]... [--keys TIMELINE] [--screen FILE] [--screen-at CYCLES=FILE]... \
+[--compare-screen FILE [--tolerance PIXELS] [--diff-image FILE]] \
+[--profile] [--folded FILE] [--trace FILE [--trace-cycles N]] [--replay FILE] <file>\n       \
+emulator --debug [--cycles N] [--set ADDRESS=VALUE]... [--replay FILE] <file>\n       \
+emulator --gdb [HOST:]PORT|--gdb-socket PATH [--cycles N] [--set ADDRESS=VALUE]... <file>\n       emulator --terminal [--charset braille|halfblock] [--clock HZ] \
+[--cycles N] [--set ADDRESS=VALUE]... <file>\n       emulator <script.tst>";
+
+/// Prints `message` with the usage and exits with status 2
+fn usage_error(message: &str) -> ! {
+    eprintln!("error: {message}\n{USAGE}");
+    process::exit(2)
+}
+
+/// Number given on the command line for `option`
+fn number<T: FromStr>(option: &str, text: &str) -> T {
+    text.parse()
+        .unwrap_or_else(|_| usage_error(&format!("bad number `{text}` for `{option}`")))
+}
+
+/// Index into the RAM given on the command line
+fn ram_address(text: &str) -> usize {
+    match text.parse() {
+        Ok(address) if address < RAM_SIZE => address,
+        _ => usage_error(&format!("`{text}` is not a RAM address below {RAM_SIZE}")),
+    }
+}
+
+fn main() {
+    let mut args = env::args().skip(1);
+    let mut max_cycles = 10_000_000;
+    let mut settings = Vec::new();
+    let mut shown = Vec::new();
+    let mut keys = None;
+    let mut screen = None;
+    let mut snapshots = Vec::new();
+    let mut reference = None;
+    let mut diff_image = None;
+    let mut tolerance = 0;
+    let mut debug = false;
+    let mut profile = false;
+    let mut terminal = false;
+    let mut charset = Charset::Braille;
+    let mut clock = 1_000_000;
+    let mut folded = None;
+    let mut trace = None;
+    let mut trace_cycles = 1_000_000;
+    let mut replay = None;
+    let mut gdb = None;
+    let mut gdb_socket = None;
+    let mut filename = None;
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .unwrap_or_else(|| usage_error(&format!("missing value for `{arg}`")))
+        };
+        match arg.as_str() {
+            "--cycles" => max_cycles = number(&arg, &value()),
+            "--set" => {
+                let setting = value();
+                let Some((address, value)) = setting.split_once('=') else {
+                    usage_error(&format!(
+                        "expected ADDRESS=VALUE for `--set`, got `{setting}`"
+                    ));
+                };
+                let address = ram_address(address);
+                let value: i16 = number(&arg, value);
+                settings.push((address, value as u16));
+            }
+            "--show" => shown.push(ram_address(&value())),
+            "--debug" => debug = true,
+            "--profile" => profile = true,
+            "--terminal" => terminal = true,
+            "--charset" => {
+                charset = match value().as_str() {
+                    "braille" => Charset::Braille,
+                    "halfblock" => Charset::HalfBlock,
+                    other => usage_error(&format!("unknown charset `{other}`")),
+                }
+            }
+            "--clock" => clock = number(&arg, &value()),
+            "--folded" => folded = Some(value()),
+            "--trace" => trace = Some(value()),
+            "--trace-cycles" => trace_cycles = number(&arg, &value()),
+            "--gdb" => gdb = Some(value()),
+            "--gdb-socket" => gdb_socket = Some(value()),
+            "--replay" => replay = Some(value()),
+            "--keys" => keys = Some(value()),
+            "--screen" => screen = Some(value()),
+            "--screen-at" => {
+                let snapshot = value();
+                let Some((cycles, file)) = snapshot.split_once('=') else {
+                    usage_error(&format!(
+                        "expected CYCLES=FILE for `--screen-at`, got `{snapshot}`"
+                    ));
+                };
+                snapshots.push((number::<u64>(&arg, cycles), file.to_owned()));
+            }
+            "--compare-screen" => reference = Some(value()),
+            "--diff-image" => diff_image = Some(value()),
+            "--tolerance" => tolerance = number(&arg, &value()),
+            _ if arg.starts_with("--") => usage_error(&format!("unknown option `{arg}`")),
+            _ if filename.is_none() => filename = Some(arg),
+            _ => usage_error(&format!("unexpected argument `{arg}`")),
+        }
+    }
+    let Some(filename) = filename else {
+        usage_error("missing file");
+    };
+    let debugging = debug || gdb.is_some() || gdb_socket.is_some();
+    let profiling = profile || folded.is_some();
+    let conflicts = [
+        (
+            keys.is_some() && debugging,
+            "--keys can't be combined with --debug or --gdb",
+        ),
+        (
+            keys.is_some() && terminal,
+            "--keys can't be combined with --terminal",
+        ),
+        (
+            profiling && trace.is_some(),
+            "--profile and --folded can't be combined with --trace",
+        ),
+        (
+            (profiling || trace.is_some()) && terminal,
+            "--profile, --folded and --trace can't be combined with --terminal",
+        ),
+        (
+            (screen.is_some() || !shown.is_empty()) && replay.is_some(),
+            "--screen and --show can't be combined with --replay",
+        ),
+    ];
+    if let Some((_, message)) = conflicts.iter().find(|(conflict, _)| *conflict) {
+        usage_error(message);
+    }
+    if filename.ends_with(".tst") {
+        match run_script(Path::new(&filename)) {
+            Ok(()) => eprintln!("End of script - Comparison ended successfully"),
+            Err(e) => {
+                eprintln!("{e}");
+                process::exit(1);
+            }
+        }
+        return;
+    }
+
+    if debugging {
+        let mut debugger = Debugger::load(Path::new(&filename)).unwrap_or_else(|errors| {
+            for e in &errors {
+                eprintln!("{e}");
+            }
+            process::exit(1);
+        });
+        debugger.max_cycles = max_cycles;
+        for (address, value) in &settings {
+            debugger.computer.ram[*address] = *value;
+        }
+        if let Some(file) = replay {
+            if let Err(e) = debugger.replay(&read_trace(&file)) {
+                eprintln!("error: {file}: {e}");
+                process::exit(1);
+            }
+            println!("replayed `{file}` up to cycle {}", debugger.computer.cycles);
+        }
+        if let Some(address) = gdb {
+            serve_tcp(&mut debugger, &address);
+        } else if let Some(path) = gdb_socket {
+            serve_unix(&mut debugger, &path);
+        } else {
+            repl(&mut debugger);
+        }
+        return;
+    }
+
+    let timeline = match keys {
+        Some(file) => Timeline::load(Path::new(&file)).unwrap_or_else(|errors| {
+            for e in &errors {
+                eprintln!("{e}");
+            }
+            process::exit(1);
+        }),
+        None => Timeline::default(),
+    };
+    let (words, symbols) = load_with_symbols(Path::new(&filename)).unwrap_or_else(|errors| {
+        for e in &errors {
+            eprintln!("{e}");
+        }
+        process::exit(1);
+    });
+    let mut computer = Computer::new(&words);
+    let mut profiler = profiling.then(|| Profiler::new(&symbols));
+    let mut recording = trace.is_some().then(|| Trace::new(&computer, trace_cycles));
+    let mut engine = Engine::new(&computer.rom);
+    let mut run_until = |computer: &mut Computer, end: u64| {
+        let cycles = end - computer.cycles;
+        match (&mut profiler, &mut recording) {
+            (Some(profiler), _) => profiler.run(computer, cycles, &timeline),
+            (_, Some(recording)) => recording.run(computer, cycles, &timeline),
+            _ => engine.run_with_keys(computer, cycles, &timeline),
+        }
+    };
+    for (address, value) in settings {
+        computer.ram[address] = value;
+    }
+    if let Some(file) = replay {
+        match emulator::replay(&mut computer, &read_trace(&file), 0) {
+            Ok(_) => eprintln!("`{file}` replayed up to cycle {}", computer.cycles),
+            Err(e) => {
+                eprintln!("error: {file}: {e}");
+                process::exit(1);
+            }
+        }
+        return;
+    }
+    if terminal {
+        match run_in_terminal(&mut computer, charset, clock, max_cycles) {
+            Ok(_) => eprintln!("stopped after {} cycles", computer.cycles),
+            Err(e) => {
+                eprintln!("error: {e}");
+                process::exit(1);
+            }
+        }
+        return;
+    }
+    // Runs up to each snapshot, a halted program keeps showing the same screen
+    snapshots.sort();
+    for (cycles, file) in snapshots.iter().filter(|(c, _)| *c < max_cycles) {
+        run_until(&mut computer, *cycles);
+        save(&computer.screenshot(), file);
+    }
+    match run_until(&mut computer, max_cycles) {
+        Stop::Halted => eprintln!("halted after {} cycles", computer.cycles),
+        Stop::CycleLimit => eprintln!("stopped after {} cycles", computer.cycles),
+    }
+    for address in shown {
+        println!("RAM[{address}] = {}", computer.ram[address] as i16);
+    }
+    if let Some(profiler) = &profiler {
+        if profile {
+            print!("{}", profiler.table());
+        }
+        if let Some(file) = folded {
+            if let Err(e) = fs::write(&file, profiler.folded()) {
+                eprintln!("error: couldn't write `{file}`: {e}");
+                process::exit(1);
+            }
+        }
+    }
+    if let (Some(file), Some(recording)) = (trace, &recording) {
+        if let Err(e) = fs::write(&file, recording.to_text()) {
+            eprintln!("error: couldn't write `{file}`: {e}");
+            process::exit(1);
+        }
+    }
+    let screenshot = computer.screenshot();
+    if let Some(file) = screen {
+        save(&screenshot, &file);
+    }
+    if let Some(file) = reference {
+        compare_screen(&screenshot, &file, tolerance, diff_image.as_deref());
+    }
+}
+
+/// Reads debugger commands until `quit` or the end of the input. An empty
+/// line repeats the previous command.
+fn repl(debugger: &mut Debugger) {
+    let mut previous = String::new();
+    let mut lines = io::stdin().lock().lines();
+    loop {
+        print!("(hack) ");
+        io::stdout().flush().expect("Writing to stdout");
+        let Some(Ok(line)) = lines.next() else {
+            println!();
+            return;
+        };
+        let line = if line.trim().is_empty() {
+            previous.clone()
+        } else {
+            line.trim().to_owned()
+        };
+        if matches!(line.as_str(), "quit" | "q") {
+            return;
+        }
+        match debugger.execute(&line) {
+            Ok(output) => print!("{output}"),
+            Err(e) => println!("error: {e}"),
+        }
+        previous = line;
+    }
+}
+
+/// Waits for a GDB client on `address`, a port on localhost if it has no
+/// host
+fn serve_tcp(debugger: &mut Debugger, address: &str) {
+    let address = if address.contains(':') {
+        address.to_owned()
+    } else {
+        format!("127.0.0.1:{address}")
+    };
+    let served = TcpListener::bind(&address).and_then(|listener| {
+        eprintln!("waiting for GDB on {}", listener.local_addr()?);
+        let (stream, _) = listener.accept()?;
+        // Packets are small and wait for each other
+        stream.set_nodelay(true)?;
+        serve_gdb(debugger, stream)
+    });
+    if let Err(e) = served {
+        eprintln!("error: {address}: {e}");
+        process::exit(1);
+    }
+}
+
+#[cfg(unix)]
+fn serve_unix(debugger: &mut Debugger, path: &str) {
+    use std::os::unix::net::UnixListener;
+
+    let listener = UnixListener::bind(path).unwrap_or_else(|e| {
+        eprintln!("error: {path}: {e}");
+        process::exit(1);
+    });
+    eprintln!("waiting for GDB on {path}");
+    let served = listener
+        .accept()
+        .and_then(|(stream, _)| serve_gdb(debugger, stream));
+    let _ = fs::remove_file(path);
+    if let Err(e) = served {
+        eprintln!("error: {path}: {e}");
+        process::exit(1);
+    }
+}
+
+#[cfg(not(unix))]
+fn serve_unix(_: &mut Debugger, _: &str) {
+    eprintln!("error: Unix sockets aren't supported on this system");
+    process::exit(1);
+}
+
+fn read_trace(file: &str) -> String {
+    fs::read_to_string(file).unwrap_or_else(|e| {
+        eprintln!("error: couldn't read `{file}`: {e}");
+        process::exit(1);
+    })
+}
+
+fn save(bitmap: &Bitmap, file: &str) {
+    if let Err(e) = bitmap.save(Path::new(file)) {
+        eprintln!("error: {e}");
+        process::exit(1);
+    }
+}
+
+/// Exits with an error if more than `tolerance` pixels differ from the
+/// reference image. A reference of another size is taken as a scaled
+/// screenshot and compared with the screen scaled to its size.
+fn compare_screen(screenshot: &Bitmap, file: &str, tolerance: usize, diff_image: Option<&str>) {
+    let difference = Bitmap::load(Path::new(file))
+        .and_then(|reference| {
+            screenshot
+                .scaled(reference.width, reference.height)
+                .difference(&reference)
+        })
+        .unwrap_or_else(|e| {
+            eprintln!("error: {e}");
+            process::exit(1);
+        });
+    if let Some(diff_image) = diff_image {
+        save(&difference.image, diff_image);
+    }
+    match difference.bounds {
+        None => eprintln!("screen matches `{file}`"),
+        Some((left, top, right, bottom)) => {
+            let message = format!(
+                "{} pixels differ from `{file}` between ({left}, {top}) and ({right}, {bottom})",
+                difference.count
+            );
+            if difference.count > tolerance {
+                eprintln!("error: {message}");
+                process::exit(1);
+            }
+            eprintln!("{message}, within the tolerance of {tolerance}");
+        }
+    }
+}
@@ -0,0 +1,244 @@
+use crate::{
+    computer::{Computer, Stop},
+    keyboard::Timeline,
+};
+
+use assembler::{SymbolKind, SymbolTable, ROM_SIZE};
+
+use std::{
+    cmp::Reverse,
+    collections::{BTreeMap, HashMap},
+};
+
+/// Name of the code before the first function label
+const START: &str = "(start)";
+
+/// Counts the instructions executed at each ROM address and the call stacks
+/// they were executed in.
+///
+/// Labels without `$` start a function, like the `Function(name, _)` labels
+/// of translated VM code, and labels with `$` belong to the function before
+/// them. A jump right before a `$ret.` label is a call, jumping back to that
+/// label returns.
+#[derive(Debug, Clone)]
+pub struct Profiler {
+    counts: Vec<u64>,
+    /// Name of the function starting at each address
+    functions: BTreeMap<u16, String>,
+    /// Addresses of the jumps of calls
+    call_sites: Vec<bool>,
+    /// Entry address of each active function with the address it returns to
+    stack: Vec<(u16, u16)>,
+    /// Cycles spent in each call stack, given as function entry addresses
+    stacks: HashMap<Vec<u16>, u64>,
+    /// Cycles spent in the current stack, not yet added to `stacks`
+    pending: u64,
+    /// Number of calls of each function
+    calls: BTreeMap<u16, u64>,
+}
+
+/// Cycles spent in a function.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FunctionProfile {
+    pub name: String,
+    /// Executing the function's own instructions
+    pub self_cycles: u64,
+    /// Between calling the function and returning from it, including other
+    /// functions it called. Recursive calls are only counted once.
+    pub inclusive_cycles: u64,
+    pub calls: u64,
+}
+
+impl Profiler {
+    /// Profiler for a program with the labels of `symbols`
+    pub fn new(symbols: &SymbolTable) -> Self {
+        let mut functions = BTreeMap::new();
+        let mut call_sites = vec![false; ROM_SIZE];
+        for (name, symbol) in symbols.iter() {
+            if symbol.kind != SymbolKind::Label {
+                continue;
+            }
+            if name.contains("$ret.") && symbol.address > 0 {
+                call_sites[usize::from(symbol.address - 1)] = true;
+            }
+            if !name.contains('$') {
+                // Of several labels at the same address, the first in
+                // alphabetical order is shown
+                functions
+                    .entry(symbol.address)
+                    .and_modify(|n: &mut String| {
+                        if name < n.as_str() {
+                            *n = name.to_owned();
+                        }
+                    })
+                    .or_insert_with(|| name.to_owned());
+            }
+        }
+        Profiler {
+            counts: vec![0; ROM_SIZE],
+            functions,
+            call_sites,
+            stack: Vec::new(),
+            stacks: HashMap::new(),
+            pending: 0,
+            calls: BTreeMap::new(),
+        }
+    }
+
+    /// Executes the instruction at PC and records it
+    pub fn step(&mut self, computer: &mut Computer) {
+        let pc = computer.pc;
+        self.counts[usize::from(pc)] += 1;
+        if self.stack.is_empty() {
+            self.stack.push((self.function_start(pc), 0x8000));
+        }
+        self.pending += 1;
+        computer.step();
+        let next = computer.pc;
+        if self.call_sites[usize::from(pc)] && next != pc + 1 {
+            self.flush();
+            let function = self.function_start(next);
+            *self.calls.entry(function).or_default() += 1;
+            self.stack.push((function, pc + 1));
+        } else if self.stack.len() > 1 && self.stack.last().is_some_and(|(_, r)| *r == next) {
+            self.flush();
+            self.stack.pop();
+        }
+    }
+
+    /// Adds the pending cycles before the stack changes
+    fn flush(&mut self) {
+        let key = self.stack_key();
+        *self.stacks.entry(key).or_default() += self.pending;
+        self.pending = 0;
+    }
+
+    fn stack_key(&self) -> Vec<u16> {
+        self.stack.iter().map(|(function, _)| *function).collect()
+    }
+
+    /// Cycles of each stack, including the pending ones
+    fn all_stacks(&self) -> Vec<(Vec<u16>, u64)> {
+        let current = self.stack_key();
+        let mut stacks: Vec<(Vec<u16>, u64)> = self
+            .stacks
+            .iter()
+            .map(|(stack, cycles)| {
+                let pending = if *stack == current { self.pending } else { 0 };
+                (stack.clone(), cycles + pending)
+            })
+            .collect();
+        if self.pending > 0 && !self.stacks.contains_key(&current) {
+            stacks.push((current, self.pending));
+        }
+        stacks
+    }
+
+    /// Like [`Computer::run_with_keys`], recording every instruction
+    pub fn run(&mut self, computer: &mut Computer, max_cycles: u64, timeline: &Timeline) -> Stop {
+        computer.run_split(max_cycles, timeline, |computer, cycles| {
+            for _ in 0..cycles {
+                if computer.is_halted() {
+                    return Stop::Halted;
+                }
+                self.step(computer);
+            }
+            if computer.is_halted() {
+                Stop::Halted
+            } else {
+                Stop::CycleLimit
+            }
+        })
+    }
+
+    /// Times the instruction at `address` was executed
+    pub fn count(&self, address: u16) -> u64 {
+        self.counts[usize::from(address)]
+    }
+
+    /// Address of the function label at or before `address`, 0 for the
+    /// code before the first one
+    fn function_start(&self, address: u16) -> u16 {
+        self.functions
+            .range(..=address)
+            .next_back()
+            .map_or(0, |(start, _)| *start)
+    }
+
+    fn name(&self, start: u16) -> &str {
+        self.functions.get(&start).map_or(START, String::as_str)
+    }
+
+    /// Every function that was executed, most self cycles first
+    pub fn functions(&self) -> Vec<FunctionProfile> {
+        let mut profiles = BTreeMap::new();
+        for (address, count) in self.counts.iter().enumerate().filter(|(_, c)| **c > 0) {
+            let start = self.function_start(address as u16);
+            self.entry(&mut profiles, start).self_cycles += count;
+        }
+        for (stack, cycles) in &self.all_stacks() {
+            for (i, start) in stack.iter().enumerate() {
+                if !stack[..i].contains(start) {
+                    self.entry(&mut profiles, *start).inclusive_cycles += cycles;
+                }
+            }
+        }
+        for (start, calls) in &self.calls {
+            self.entry(&mut profiles, *start).calls = *calls;
+        }
+        let mut profiles: Vec<FunctionProfile> = profiles.into_values().collect();
+        profiles.sort_by(|a, b| {
+            (Reverse(a.self_cycles), &a.name).cmp(&(Reverse(b.self_cycles), &b.name))
+        });
+        profiles
+    }
+
+    fn entry<'a>(
+        &self,
+        profiles: &'a mut BTreeMap<u16, FunctionProfile>,
+        start: u16,
+    ) -> &'a mut FunctionProfile {
+        profiles.entry(start).or_insert_with(|| FunctionProfile {
+            name: self.name(start).to_owned(),
+            self_cycles: 0,
+            inclusive_cycles: 0,
+            calls: 0,
+        })
+    }
+
+    /// Table of [`Profiler::functions`] with their share of all cycles
+    pub fn table(&self) -> String {
+        let total: u64 = self.counts.iter().sum();
+        let share = |cycles: u64| 100.0 * cycles as f64 / total.max(1) as f64;
+        let mut result =
+            String::from("      self   share   inclusive   share     calls  function\n");
+        for p in self.functions() {
+            result += &format!(
+                "{:10}  {:5.1}%  {:10}  {:5.1}%  {:8}  {}\n",
+                p.self_cycles,
+                share(p.self_cycles),
+                p.inclusive_cycles,
+                share(p.inclusive_cycles),
+                p.calls,
+                p.name
+            );
+        }
+        result += &format!("{total:10}  100.0%                                cycles total\n");
+        result
+    }
+
+    /// One line per call stack like `Sys.init;Main.main;Math.multiply 1234`,
+    /// the format of `flamegraph.pl` and `inferno-flamegraph`
+    pub fn folded(&self) -> String {
+        let mut lines: Vec<String> = self
+            .all_stacks()
+            .iter()
+            .map(|(stack, cycles)| {
+                let names: Vec<&str> = stack.iter().map(|start| self.name(*start)).collect();
+                format!("{} {cycles}\n", names.join(";"))
+            })
+            .collect();
+        lines.sort();
+        lines.concat()
+    }
+}
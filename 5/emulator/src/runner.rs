@@ -0,0 +1,403 @@
+use crate::{
+    computer::{Computer, RAM_SIZE},
+    error::{ScriptError, ScriptErrorKind},
+    load_program,
+    script::{parse_script, Column, Command, Statement},
+    vm::VmEmulator,
+};
+
+use assembler::ROM_SIZE;
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+/// Runs a `*.tst` script on the native emulators and compares its output with
+/// the compare file given by `compare-to`, failing at the first difference.
+///
+/// Scripts for the CPU emulator (`load Prog.asm`), the VM emulator (`load
+/// Prog.vm` and `vmstep`) and `Computer.hdl` are supported, other chips need
+/// the hardware simulator. The output is written to the `output-file`.
+pub fn run_script(file: &Path) -> Result<(), ScriptError> {
+    let error = |line, kind| ScriptError {
+        file: file.to_owned(),
+        line,
+        kind,
+    };
+    let source = fs::read_to_string(file).map_err(|e| {
+        error(
+            None,
+            ScriptErrorKind::Io(format!("couldn't read file: {e}")),
+        )
+    })?;
+    let statements = parse_script(&source).map_err(|(line, kind)| error(Some(line), kind))?;
+    let mut runner = Runner {
+        script: file.to_owned(),
+        dir: file.parent().unwrap_or(Path::new("")).to_owned(),
+        target: None,
+        columns: Vec::new(),
+        output_file: None,
+        output: Vec::new(),
+        compare_file: None,
+        expected: Vec::new(),
+        time: 0,
+        tick: false,
+    };
+    let result = runner.run(&statements).and_then(|()| runner.finish());
+    let written = runner.write_output();
+    result.and(written)
+}
+
+enum Target {
+    /// CPU emulator, or the `Computer` chip if `chip` is set, which names its
+    /// variables after its parts, e.g. `RAM16K[0]` or `ARegister[]`
+    Cpu {
+        computer: Computer,
+        chip: bool,
+        reset: bool,
+    },
+    Vm(VmEmulator),
+}
+
+struct Runner {
+    script: PathBuf,
+    /// Files in the script are relative to its directory
+    dir: PathBuf,
+    target: Option<Target>,
+    columns: Vec<Column>,
+    output_file: Option<PathBuf>,
+    output: Vec<String>,
+    compare_file: Option<PathBuf>,
+    expected: Vec<String>,
+    /// Clock cycles, `tick` is set between a tick and the following tock
+    time: u64,
+    tick: bool,
+}
+
+impl Runner {
+    fn run(&mut self, statements: &[Statement]) -> Result<(), ScriptError> {
+        for s in statements {
+            match &s.command {
+                Command::Repeat(count, body) => {
+                    for _ in 0..*count {
+                        self.run(body)?;
+                    }
+                }
+                Command::While(condition, body) => loop {
+                    let value = self
+                        .get(&condition.variable)
+                        .map_err(|kind| self.error(s, kind))?;
+                    if !condition.operator.holds(value, condition.value) {
+                        break;
+                    }
+                    self.run(body)?;
+                },
+                _ => self.execute(s).map_err(|kind| self.error(s, kind))?,
+            }
+        }
+        Ok(())
+    }
+
+    /// Comparison failures point at the compare file, everything else at the
+    /// statement
+    fn error(&self, s: &Statement, kind: ScriptErrorKind) -> ScriptError {
+        match kind {
+            ScriptErrorKind::Mismatch { .. } | ScriptErrorKind::ExtraOutput(_) => ScriptError {
+                file: self.compare_file.clone().unwrap_or_default(),
+                line: Some(self.output.len()),
+                kind,
+            },
+            _ => ScriptError {
+                file: self.script.clone(),
+                line: Some(s.line),
+                kind,
+            },
+        }
+    }
+
+    fn execute(&mut self, s: &Statement) -> Result<(), ScriptErrorKind> {
+        match &s.command {
+            Command::Load(file) => self.load(file.as_deref())?,
+            Command::RomLoad(file) => {
+                let Some(Target::Cpu { computer, .. }) = &mut self.target else {
+                    return Err(ScriptErrorKind::NothingLoaded);
+                };
+                let program = load_program(&self.dir.join(file)).map_err(load_error)?;
+                computer.rom[..program.len()].copy_from_slice(&program);
+                computer.rom[program.len()..].fill(0);
+            }
+            Command::OutputFile(file) => self.output_file = Some(self.dir.join(file)),
+            Command::CompareTo(file) => {
+                let path = self.dir.join(file);
+                let source = fs::read_to_string(&path)
+                    .map_err(|e| ScriptErrorKind::Io(format!("couldn't read `{file}`: {e}")))?;
+                self.expected = source.lines().map(str::to_owned).collect();
+                self.compare_file = Some(path);
+            }
+            Command::OutputList(columns) => {
+                self.columns = columns.clone();
+                let header = columns.iter().map(|c| c.format.header(&c.name)).collect();
+                self.output_line(header)?;
+            }
+            Command::Set(variable, value) => self.set(variable, *value)?,
+            Command::Repeat(..) | Command::While(..) => unreachable!("Handled by `run`"),
+            Command::Tick => self.tick()?,
+            Command::Tock => self.tock()?,
+            Command::TickTock => {
+                self.tick()?;
+                self.tock()?;
+            }
+            Command::VmStep => match &mut self.target {
+                Some(Target::Vm(vm)) => vm.step(),
+                _ => {
+                    return Err(ScriptErrorKind::Unsupported(
+                        "`vmstep` without VM code".to_owned(),
+                    ))
+                }
+            },
+            Command::Output => {
+                let columns = self.columns.clone();
+                let fields = columns
+                    .iter()
+                    .map(|c| {
+                        Ok(if c.name == "time" {
+                            let time = format!("{}{}", self.time, if self.tick { "+" } else { "" });
+                            c.format.text(&time)
+                        } else {
+                            c.format.number(self.get(&c.name)?)
+                        })
+                    })
+                    .collect::<Result<_, ScriptErrorKind>>()?;
+                self.output_line(fields)?;
+            }
+            // The emulators don't need combinational logic to be evaluated and
+            // messages are addressed at users of the graphical simulators
+            Command::Eval | Command::Echo(_) | Command::ClearEcho => {}
+        }
+        Ok(())
+    }
+
+    fn load(&mut self, file: Option<&str>) -> Result<(), ScriptErrorKind> {
+        let path = file.map_or_else(|| self.dir.clone(), |f| self.dir.join(f));
+        let extension = path
+            .extension()
+            .and_then(|e| e.to_str())
+            .unwrap_or_default();
+        self.target = Some(match extension {
+            "asm" | "hack" => Target::Cpu {
+                computer: Computer::new(&load_program(&path).map_err(load_error)?),
+                chip: false,
+                reset: false,
+            },
+            "hdl" if path.file_stem().is_some_and(|s| s == "Computer") => Target::Cpu {
+                computer: Computer::new(&[]),
+                chip: true,
+                reset: false,
+            },
+            "hdl" => {
+                return Err(ScriptErrorKind::Unsupported(format!(
+                    "simulating the chip `{}`",
+                    file.unwrap_or_default()
+                )))
+            }
+            _ => Target::Vm(VmEmulator::load(&path).map_err(ScriptErrorKind::Load)?),
+        });
+        self.time = 0;
+        self.tick = false;
+        Ok(())
+    }
+
+    fn tick(&mut self) -> Result<(), ScriptErrorKind> {
+        if self.target.is_none() {
+            return Err(ScriptErrorKind::NothingLoaded);
+        }
+        self.tick = true;
+        Ok(())
+    }
+
+    /// Registers take their new values at the end of a clock cycle
+    fn tock(&mut self) -> Result<(), ScriptErrorKind> {
+        match &mut self.target {
+            Some(Target::Cpu {
+                computer, reset, ..
+            }) => {
+                computer.step();
+                if *reset {
+                    computer.reset();
+                }
+            }
+            Some(Target::Vm(_)) => {}
+            None => return Err(ScriptErrorKind::NothingLoaded),
+        }
+        self.time += 1;
+        self.tick = false;
+        Ok(())
+    }
+
+    fn get(&self, variable: &str) -> Result<u16, ScriptErrorKind> {
+        let unknown = || ScriptErrorKind::UnknownVariable(variable.to_owned());
+        let (name, index) = split_index(variable).ok_or_else(unknown)?;
+        Ok(
+            match self.target.as_ref().ok_or(ScriptErrorKind::NothingLoaded)? {
+                Target::Cpu {
+                    computer,
+                    chip,
+                    reset,
+                } => match (*chip, name, index) {
+                    (false, "A", None) | (true, "ARegister", Some(None | Some(0))) => computer.a,
+                    (false, "D", None) | (true, "DRegister", Some(None | Some(0))) => computer.d,
+                    (false, "PC", None) | (true, "PC", Some(None | Some(0))) => computer.pc,
+                    (true, "reset", None) => u16::from(*reset),
+                    (false, "RAM", Some(Some(i))) | (true, "RAM16K", Some(Some(i))) => {
+                        *computer.ram.get(i).ok_or_else(unknown)?
+                    }
+                    (false, "ROM", Some(Some(i))) | (true, "ROM32K", Some(Some(i))) => {
+                        *computer.rom.get(i).ok_or_else(unknown)?
+                    }
+                    _ => return Err(unknown()),
+                },
+                Target::Vm(vm) => vm.ram[vm_address(vm, name, index).ok_or_else(unknown)?],
+            },
+        )
+    }
+
+    fn set(&mut self, variable: &str, value: u16) -> Result<(), ScriptErrorKind> {
+        let unknown = || ScriptErrorKind::UnknownVariable(variable.to_owned());
+        let (name, index) = split_index(variable).ok_or_else(unknown)?;
+        match self.target.as_mut().ok_or(ScriptErrorKind::NothingLoaded)? {
+            Target::Cpu {
+                computer,
+                chip,
+                reset,
+            } => match (*chip, name, index) {
+                (false, "A", None) | (true, "ARegister", Some(None | Some(0))) => {
+                    computer.a = value
+                }
+                (false, "D", None) | (true, "DRegister", Some(None | Some(0))) => {
+                    computer.d = value
+                }
+                (false, "PC", None) | (true, "PC", Some(None | Some(0))) => {
+                    computer.pc = value & (ROM_SIZE as u16 - 1)
+                }
+                (true, "reset", None) => *reset = value != 0,
+                (false, "RAM", Some(Some(i))) | (true, "RAM16K", Some(Some(i))) => {
+                    *computer.ram.get_mut(i).ok_or_else(unknown)? = value
+                }
+                (false, "ROM", Some(Some(i))) | (true, "ROM32K", Some(Some(i))) => {
+                    *computer.rom.get_mut(i).ok_or_else(unknown)? = value
+                }
+                _ => return Err(unknown()),
+            },
+            Target::Vm(vm) => {
+                let address = vm_address(vm, name, index).ok_or_else(unknown)?;
+                vm.ram[address] = value;
+            }
+        }
+        Ok(())
+    }
+
+    /// Appends `fields` as a line of the output and compares it
+    fn output_line(&mut self, fields: Vec<String>) -> Result<(), ScriptErrorKind> {
+        let line = format!("|{}|", fields.join("|"));
+        self.output.push(line.clone());
+        if self.compare_file.is_none() {
+            return Ok(());
+        }
+        let Some(expected) = self.expected.get(self.output.len() - 1) else {
+            return Err(ScriptErrorKind::ExtraOutput(line));
+        };
+        let expected_fields: Vec<&str> = expected.trim_matches('|').split('|').collect();
+        let difference = (0..fields.len().max(expected_fields.len())).find(|i| {
+            match (fields.get(*i), expected_fields.get(*i)) {
+                (Some(actual), Some(expected)) => !matches(expected, actual),
+                _ => true,
+            }
+        });
+        match difference {
+            Some(i) => Err(ScriptErrorKind::Mismatch {
+                column: i + 1,
+                name: self
+                    .columns
+                    .get(i)
+                    .map(|c| c.name.clone())
+                    .unwrap_or_default(),
+                expected: expected_fields
+                    .get(i)
+                    .copied()
+                    .unwrap_or_default()
+                    .to_owned(),
+                actual: fields.get(i).cloned().unwrap_or_default(),
+            }),
+            None => Ok(()),
+        }
+    }
+
+    fn finish(&self) -> Result<(), ScriptError> {
+        match self.expected.get(self.output.len()) {
+            Some(line) if self.compare_file.is_some() => Err(ScriptError {
+                file: self.compare_file.clone().unwrap_or_default(),
+                line: Some(self.output.len() + 1),
+                kind: ScriptErrorKind::MissingOutput(line.clone()),
+            }),
+            _ => Ok(()),
+        }
+    }
+
+    fn write_output(&self) -> Result<(), ScriptError> {
+        let Some(file) = &self.output_file else {
+            return Ok(());
+        };
+        let output: String = self.output.iter().map(|l| format!("{l}\n")).collect();
+        fs::write(file, output).map_err(|e| ScriptError {
+            file: file.clone(),
+            line: None,
+            kind: ScriptErrorKind::Io(format!("couldn't write file: {e}")),
+        })
+    }
+}
+
+/// `*` in the compare file matches any character
+fn matches(expected: &str, actual: &str) -> bool {
+    expected.len() == actual.len()
+        && expected
+            .chars()
+            .zip(actual.chars())
+            .all(|(e, a)| e == '*' || e == a)
+}
+
+/// `RAM[5]` is `("RAM", Some(Some(5)))`, `PC[]` is `("PC", Some(None))`
+fn split_index(variable: &str) -> Option<(&str, Option<Option<usize>>)> {
+    let Some((name, index)) = variable.split_once('[') else {
+        return Some((variable, None));
+    };
+    let index = index.strip_suffix(']')?;
+    if index.is_empty() {
+        return Some((name, Some(None)));
+    }
+    Some((name, Some(Some(index.parse().ok()?))))
+}
+
+/// RAM address of a VM emulator variable like `sp` or `argument[1]`
+fn vm_address(vm: &VmEmulator, name: &str, index: Option<Option<usize>>) -> Option<usize> {
+    let pointer = match name {
+        "sp" => 0,
+        "local" => 1,
+        "argument" => 2,
+        "this" => 3,
+        "that" => 4,
+        "temp" => return Some(5 + index??).filter(|a| *a < 13),
+        "RAM" => return Some(index??).filter(|a| *a < RAM_SIZE),
+        _ => return None,
+    };
+    match index {
+        None => Some(pointer),
+        Some(Some(i)) if pointer != 0 => Some((usize::from(vm.ram[pointer]) + i) % RAM_SIZE),
+        _ => None,
+    }
+}
+
+fn load_error(errors: Vec<assembler::AsmError>) -> ScriptErrorKind {
+    let messages: Vec<String> = errors.iter().map(ToString::to_string).collect();
+    ScriptErrorKind::Load(messages.join("\n"))
+}
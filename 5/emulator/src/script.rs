@@ -0,0 +1,394 @@
+use crate::error::ScriptErrorKind;
+
+use std::str::FromStr;
+
+/// A command of a test script with the line it starts on.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Statement {
+    pub command: Command,
+    pub line: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Command {
+    /// `load` without a file loads every `*.vm` file of the script's directory
+    Load(Option<String>),
+    /// `ROM32K load Prog.hack`, used by the `Computer.hdl` scripts
+    RomLoad(String),
+    OutputFile(String),
+    CompareTo(String),
+    OutputList(Vec<Column>),
+    Set(String, u16),
+    Repeat(u64, Vec<Statement>),
+    While(Condition, Vec<Statement>),
+    Tick,
+    Tock,
+    TickTock,
+    VmStep,
+    Eval,
+    Output,
+    Echo(String),
+    ClearEcho,
+}
+
+/// Variable of an `output-list` with its format, like `RAM[0]%D1.6.1`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Column {
+    pub name: String,
+    pub format: Format,
+}
+
+/// `%D1.6.1` is decimal with one space of padding on each side of a six
+/// character field.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Format {
+    pub radix: Radix,
+    pub left: usize,
+    pub len: usize,
+    pub right: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Radix {
+    Decimal,
+    Hex,
+    Binary,
+    /// Text like the `time` variable, left aligned
+    String,
+}
+
+/// `variable op value` of a `while` loop
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Condition {
+    pub variable: String,
+    pub operator: Comparison,
+    pub value: u16,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Comparison {
+    Equal,
+    NotEqual,
+    Less,
+    Greater,
+    LessOrEqual,
+    GreaterOrEqual,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Word(String),
+    /// Quoted text of `echo`
+    Text(String),
+    /// `,`, `;` and the breakpoint marker `!` end a command
+    End,
+    Open,
+    Close,
+}
+
+/// Parses a test script, errors come with the line they were found on.
+pub fn parse_script(source: &str) -> Result<Vec<Statement>, (usize, ScriptErrorKind)> {
+    let tokens = tokenize(source)?;
+    let mut parser = Parser { tokens, next: 0 };
+    let statements = parser.block()?;
+    match parser.tokens.get(parser.next) {
+        Some((line, _)) => Err((*line, syntax("unmatched `}`"))),
+        None => Ok(statements),
+    }
+}
+
+fn syntax(message: &str) -> ScriptErrorKind {
+    ScriptErrorKind::Syntax(message.to_owned())
+}
+
+fn tokenize(source: &str) -> Result<Vec<(usize, Token)>, (usize, ScriptErrorKind)> {
+    let mut tokens = Vec::new();
+    let mut line = 1;
+    let mut chars = source.chars().peekable();
+    let mut word = String::new();
+    while let Some(c) = chars.next() {
+        let token = match c {
+            '/' if chars.peek() == Some(&'/') => {
+                while chars.next_if(|c| *c != '\n').is_some() {}
+                None
+            }
+            '/' if chars.peek() == Some(&'*') => {
+                let start = line;
+                chars.next();
+                let mut previous = ' ';
+                loop {
+                    match chars.next() {
+                        Some('/') if previous == '*' => break,
+                        Some(c) => {
+                            line += usize::from(c == '\n');
+                            previous = c;
+                        }
+                        None => return Err((start, syntax("unclosed `/*` comment"))),
+                    }
+                }
+                None
+            }
+            '"' => {
+                let start = line;
+                let mut text = String::new();
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some(c) => {
+                            line += usize::from(c == '\n');
+                            text.push(c);
+                        }
+                        None => return Err((start, syntax("unclosed string"))),
+                    }
+                }
+                Some(Token::Text(text))
+            }
+            ',' | ';' | '!' => Some(Token::End),
+            '{' => Some(Token::Open),
+            '}' => Some(Token::Close),
+            c if c.is_whitespace() => None,
+            c => {
+                word.push(c);
+                continue;
+            }
+        };
+        if !word.is_empty() {
+            tokens.push((line, Token::Word(std::mem::take(&mut word))));
+        }
+        tokens.extend(token.map(|t| (line, t)));
+        line += usize::from(c == '\n');
+    }
+    if !word.is_empty() {
+        tokens.push((line, Token::Word(word)));
+    }
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<(usize, Token)>,
+    next: usize,
+}
+
+impl Parser {
+    /// Statements up to the end or a closing `}`, which is not consumed
+    fn block(&mut self) -> Result<Vec<Statement>, (usize, ScriptErrorKind)> {
+        let mut statements = Vec::new();
+        while let Some((line, token)) = self.tokens.get(self.next).cloned() {
+            match token {
+                Token::Close => break,
+                Token::End => self.next += 1,
+                _ => {
+                    let command = self.command(line)?;
+                    statements.push(Statement { command, line });
+                }
+            }
+        }
+        Ok(statements)
+    }
+
+    fn command(&mut self, line: usize) -> Result<Command, (usize, ScriptErrorKind)> {
+        let mut words = Vec::new();
+        let mut text = None;
+        let mut opens_block = false;
+        while let Some((_, token)) = self.tokens.get(self.next).cloned() {
+            self.next += 1;
+            match token {
+                Token::Word(word) => words.push(word),
+                Token::Text(t) => text = Some(t),
+                Token::End => break,
+                Token::Open => {
+                    opens_block = true;
+                    break;
+                }
+                Token::Close => return Err((line, syntax("unexpected `}`"))),
+            }
+        }
+        let words: Vec<&str> = words.iter().map(String::as_str).collect();
+        if opens_block {
+            let body = self.block()?;
+            if self.tokens.get(self.next).map(|(_, t)| t) != Some(&Token::Close) {
+                return Err((line, syntax("missing `}`")));
+            }
+            self.next += 1;
+            return block_command(&words, body).map_err(|e| (line, e));
+        }
+        simple_command(&words, text).map_err(|e| (line, e))
+    }
+}
+
+fn block_command(words: &[&str], body: Vec<Statement>) -> Result<Command, ScriptErrorKind> {
+    Ok(match words[..] {
+        ["repeat", count] => Command::Repeat(
+            count
+                .parse()
+                .map_err(|_| ScriptErrorKind::BadValue(count.to_owned()))?,
+            body,
+        ),
+        ["while", variable, operator, value] => Command::While(
+            Condition {
+                variable: variable.to_owned(),
+                operator: operator.parse()?,
+                value: parse_value(value)?,
+            },
+            body,
+        ),
+        ["repeat"] => {
+            return Err(ScriptErrorKind::Unsupported(
+                "`repeat` without count".to_owned(),
+            ))
+        }
+        _ => return Err(syntax("expected `repeat N {` or `while condition {`")),
+    })
+}
+
+fn simple_command(words: &[&str], text: Option<String>) -> Result<Command, ScriptErrorKind> {
+    Ok(match words[..] {
+        ["load"] => Command::Load(None),
+        ["load", file] => Command::Load(Some(file.to_owned())),
+        ["ROM32K", "load", file] => Command::RomLoad(file.to_owned()),
+        ["output-file", file] => Command::OutputFile(file.to_owned()),
+        ["compare-to", file] => Command::CompareTo(file.to_owned()),
+        ["output-list", ref columns @ ..] => Command::OutputList(
+            columns
+                .iter()
+                .map(|c| c.parse())
+                .collect::<Result<_, _>>()?,
+        ),
+        ["set", variable, value] => Command::Set(variable.to_owned(), parse_value(value)?),
+        ["tick"] => Command::Tick,
+        ["tock"] => Command::Tock,
+        ["ticktock"] => Command::TickTock,
+        ["vmstep"] => Command::VmStep,
+        ["eval"] => Command::Eval,
+        ["output"] => Command::Output,
+        ["echo"] => Command::Echo(text.unwrap_or_default()),
+        ["clear-echo"] => Command::ClearEcho,
+        _ => return Err(syntax(&format!("unknown command `{}`", words.join(" ")))),
+    })
+}
+
+/// Numbers are decimal unless prefixed with `%X`, `%B` or `%D`
+pub fn parse_value(text: &str) -> Result<u16, ScriptErrorKind> {
+    let bad = || ScriptErrorKind::BadValue(text.to_owned());
+    let value = if let Some(hex) = text.strip_prefix("%X") {
+        u16::from_str_radix(hex, 16).map_err(|_| bad())?
+    } else if let Some(bin) = text.strip_prefix("%B") {
+        u16::from_str_radix(bin, 2).map_err(|_| bad())?
+    } else {
+        let decimal = text.strip_prefix("%D").unwrap_or(text);
+        let value: i32 = decimal.parse().map_err(|_| bad())?;
+        if !(-32768..=65535).contains(&value) {
+            return Err(bad());
+        }
+        value as u16
+    };
+    Ok(value)
+}
+
+impl FromStr for Column {
+    type Err = ScriptErrorKind;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let bad = || ScriptErrorKind::BadValue(s.to_owned());
+        let (name, format) = s.split_once('%').ok_or_else(bad)?;
+        let mut chars = format.chars();
+        let radix = match chars.next() {
+            Some('D') => Radix::Decimal,
+            Some('X') => Radix::Hex,
+            Some('B') => Radix::Binary,
+            Some('S') => Radix::String,
+            _ => return Err(bad()),
+        };
+        let widths: Vec<usize> = chars
+            .as_str()
+            .split('.')
+            .map(|w| w.parse().map_err(|_| bad()))
+            .collect::<Result<_, _>>()?;
+        let [left, len, right] = widths[..] else {
+            return Err(bad());
+        };
+        Ok(Column {
+            name: name.to_owned(),
+            format: Format {
+                radix,
+                left,
+                len,
+                right,
+            },
+        })
+    }
+}
+
+impl Format {
+    /// Column of an output line, without the `|` separators
+    pub fn number(&self, value: u16) -> String {
+        let len = self.len;
+        let text = match self.radix {
+            Radix::Decimal => format!("{:>len$}", value as i16),
+            // Only the lowest digits are shown if the field is too narrow
+            Radix::Hex => last_chars(format!("{value:04X}"), len),
+            Radix::Binary => last_chars(format!("{value:016b}"), len),
+            Radix::String => format!("{:<len$}", value as i16),
+        };
+        self.pad(&text)
+    }
+
+    pub fn text(&self, text: &str) -> String {
+        let len = self.len;
+        self.pad(&format!("{text:<len$}"))
+    }
+
+    /// Name of the column, centered and cut to the column's width
+    pub fn header(&self, name: &str) -> String {
+        let width = self.left + self.len + self.right;
+        let name: String = name.chars().take(width).collect();
+        let left = (width - name.chars().count()) / 2;
+        format!("{:left$}{name:<rest$}", "", rest = width - left)
+    }
+
+    fn pad(&self, text: &str) -> String {
+        format!(
+            "{:left$}{text}{:right$}",
+            "",
+            "",
+            left = self.left,
+            right = self.right
+        )
+    }
+}
+
+fn last_chars(text: String, len: usize) -> String {
+    let skip = text.chars().count().saturating_sub(len);
+    format!("{:0>len$}", &text[skip..])
+}
+
+impl FromStr for Comparison {
+    type Err = ScriptErrorKind;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "=" => Comparison::Equal,
+            "<>" => Comparison::NotEqual,
+            "<" => Comparison::Less,
+            ">" => Comparison::Greater,
+            "<=" => Comparison::LessOrEqual,
+            ">=" => Comparison::GreaterOrEqual,
+            _ => return Err(syntax(&format!("unknown comparison `{s}`"))),
+        })
+    }
+}
+
+impl Comparison {
+    /// Compares as signed 16-bit numbers
+    pub fn holds(self, lhs: u16, rhs: u16) -> bool {
+        let (lhs, rhs) = (lhs as i16, rhs as i16);
+        match self {
+            Comparison::Equal => lhs == rhs,
+            Comparison::NotEqual => lhs != rhs,
+            Comparison::Less => lhs < rhs,
+            Comparison::Greater => lhs > rhs,
+            Comparison::LessOrEqual => lhs <= rhs,
+            Comparison::GreaterOrEqual => lhs >= rhs,
+        }
+    }
+}
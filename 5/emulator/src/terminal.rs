@@ -0,0 +1,209 @@
+use crate::{
+    computer::{Computer, Stop},
+    image::Bitmap,
+    keyboard::key_code,
+};
+
+use crossterm::{
+    cursor,
+    event::{
+        self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers, KeyboardEnhancementFlags,
+        PopKeyboardEnhancementFlags, PushKeyboardEnhancementFlags,
+    },
+    execute, queue,
+    terminal::{self, ClearType, EnterAlternateScreen, LeaveAlternateScreen},
+};
+
+use std::{
+    io::{self, Write},
+    time::{Duration, Instant},
+};
+
+const FRAMES_PER_SECOND: u32 = 30;
+/// Terminals without key release events only repeat held keys, a key counts
+/// as released this long after its last repetition
+const KEY_HOLD: Duration = Duration::from_millis(150);
+
+/// How pixels are drawn with text.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Charset {
+    /// 2x4 pixels per character, the screen takes 256x64 characters
+    Braille,
+    /// 1x2 pixels per character with `▀` and `▄`, 512x128 characters
+    HalfBlock,
+}
+
+/// Lines of text showing `bitmap`
+pub fn render(bitmap: &Bitmap, charset: Charset) -> Vec<String> {
+    let (width, height) = match charset {
+        Charset::Braille => (2, 4),
+        Charset::HalfBlock => (1, 2),
+    };
+    let pixel = |x: usize, y: usize| x < bitmap.width && y < bitmap.height && bitmap.get(x, y);
+    (0..bitmap.height.div_ceil(height))
+        .map(|row| {
+            (0..bitmap.width.div_ceil(width))
+                .map(|column| {
+                    let (x, y) = (column * width, row * height);
+                    match charset {
+                        Charset::Braille => {
+                            // Bits of the dots, column by column, the bottom
+                            // row came last to Unicode
+                            const DOTS: [(usize, usize, u32); 8] = [
+                                (0, 0, 0x01),
+                                (0, 1, 0x02),
+                                (0, 2, 0x04),
+                                (1, 0, 0x08),
+                                (1, 1, 0x10),
+                                (1, 2, 0x20),
+                                (0, 3, 0x40),
+                                (1, 3, 0x80),
+                            ];
+                            let bits = DOTS
+                                .iter()
+                                .filter(|(dx, dy, _)| pixel(x + dx, y + dy))
+                                .fold(0, |bits, (_, _, bit)| bits | bit);
+                            char::from_u32(0x2800 + bits).expect("Braille patterns")
+                        }
+                        Charset::HalfBlock => match (pixel(x, y), pixel(x, y + 1)) {
+                            (true, true) => '█',
+                            (true, false) => '▀',
+                            (false, true) => '▄',
+                            (false, false) => ' ',
+                        },
+                    }
+                })
+                .collect()
+        })
+        .collect()
+}
+
+/// Hack code of a key pressed in the terminal
+pub fn hack_key(key: &KeyEvent) -> Option<u16> {
+    let name = match key.code {
+        KeyCode::Char(c) => return key_code(&c.to_string()),
+        KeyCode::F(n) => return key_code(&format!("f{n}")),
+        KeyCode::Enter => "enter",
+        KeyCode::Backspace => "backspace",
+        KeyCode::Left => "left",
+        KeyCode::Up => "up",
+        KeyCode::Right => "right",
+        KeyCode::Down => "down",
+        KeyCode::Home => "home",
+        KeyCode::End => "end",
+        KeyCode::PageUp => "pageup",
+        KeyCode::PageDown => "pagedown",
+        KeyCode::Insert => "insert",
+        KeyCode::Delete => "delete",
+        KeyCode::Esc => "esc",
+        _ => return None,
+    };
+    key_code(name)
+}
+
+/// Runs `computer` in the terminal, drawing the screen after every frame and
+/// putting pressed keys into the keyboard register, until `Ctrl-C` is
+/// pressed or `max_cycles` are executed. `clock` is in instructions per
+/// second.
+pub fn run_in_terminal(
+    computer: &mut Computer,
+    charset: Charset,
+    clock: u64,
+    max_cycles: u64,
+) -> io::Result<Stop> {
+    let mut out = io::stdout();
+    terminal::enable_raw_mode()?;
+    // Restores the terminal however the loop ends
+    struct Restore(bool);
+    impl Drop for Restore {
+        fn drop(&mut self) {
+            let mut out = io::stdout();
+            if self.0 {
+                let _ = execute!(out, PopKeyboardEnhancementFlags);
+            }
+            let _ = execute!(out, cursor::Show, LeaveAlternateScreen);
+            let _ = terminal::disable_raw_mode();
+        }
+    }
+    let releases = terminal::supports_keyboard_enhancement().unwrap_or(false);
+    let _restore = Restore(releases);
+    execute!(out, EnterAlternateScreen, cursor::Hide)?;
+    if releases {
+        execute!(
+            out,
+            PushKeyboardEnhancementFlags(KeyboardEnhancementFlags::REPORT_EVENT_TYPES)
+        )?;
+    }
+
+    let frame = Duration::from_secs(1) / FRAMES_PER_SECOND;
+    let cycles_per_frame = (clock / u64::from(FRAMES_PER_SECOND)).max(1);
+    let end = computer.cycles.saturating_add(max_cycles);
+    let mut last_press = Instant::now();
+    let mut shown: Option<Vec<u16>> = None;
+    loop {
+        let start = Instant::now();
+        let stop = computer.run(cycles_per_frame.min(end - computer.cycles));
+        let finished = stop == Stop::Halted || computer.cycles == end;
+        if shown.as_deref() != Some(computer.screen()) || finished {
+            draw(&mut out, computer, charset, clock, finished)?;
+            shown = Some(computer.screen().to_vec());
+        }
+        let deadline = start + frame;
+        loop {
+            let now = Instant::now();
+            if !finished && now >= deadline {
+                break;
+            }
+            let timeout = if finished {
+                Duration::from_secs(3600)
+            } else {
+                deadline - now
+            };
+            if !event::poll(timeout)? {
+                continue;
+            }
+            let Event::Key(key) = event::read()? else {
+                continue;
+            };
+            if key.code == KeyCode::Char('c') && key.modifiers.contains(KeyModifiers::CONTROL) {
+                return Ok(stop);
+            }
+            if finished {
+                continue;
+            }
+            match key.kind {
+                KeyEventKind::Release => computer.set_key(0),
+                _ => {
+                    if let Some(code) = hack_key(&key) {
+                        computer.set_key(code);
+                        last_press = Instant::now();
+                    }
+                }
+            }
+        }
+        if !releases && last_press.elapsed() > KEY_HOLD {
+            computer.set_key(0);
+        }
+    }
+}
+
+fn draw(
+    out: &mut impl Write,
+    computer: &Computer,
+    charset: Charset,
+    clock: u64,
+    finished: bool,
+) -> io::Result<()> {
+    queue!(out, cursor::MoveTo(0, 0))?;
+    for line in render(&computer.screenshot(), charset) {
+        write!(out, "{line}\r\n")?;
+    }
+    let state = if finished { "stopped" } else { "running" };
+    write!(
+        out,
+        "{state} at cycle {}, {clock} Hz, Ctrl-C quits",
+        computer.cycles
+    )?;
+    queue!(out, terminal::Clear(ClearType::UntilNewLine))?;
+    out.flush()
+}
@@ -0,0 +1,242 @@
+use crate::{
+    computer::{Computer, Stop, KBD},
+    keyboard::Timeline,
+};
+
+use std::{collections::VecDeque, fmt::Write};
+
+/// Records the executed instructions, so that they can be undone, exported
+/// as text and replayed.
+///
+/// Only the last `capacity` instructions are kept, changes of the keyboard
+/// register are all kept, so that a run can be replayed from the start. The
+/// text format has a line per instruction with the cycle, the PC and the RAM
+/// word it wrote, and a line per key change:
+///
+/// ```text
+/// // Hack trace of cycles 0 to 3
+/// 0 0
+/// 1 1 RAM[0]=-1
+/// 2 key 131
+/// 2 2
+/// 3 3 RAM[16]=7
+/// ```
+///
+/// Key codes are 0 for releases. Runs of different builds of a program can be
+/// compared by diffing their traces.
+#[derive(Debug, Clone)]
+pub struct Trace {
+    /// Cycle of the first kept step
+    start: u64,
+    steps: VecDeque<Step>,
+    capacity: usize,
+    /// Cycle of each change of the keyboard register, with the key before and
+    /// after it
+    keys: Vec<(u64, u16, u16)>,
+    /// Key changes that were undone, made again when stepping forward to
+    /// their cycle, the next one last
+    redo: Vec<(u64, u16, u16)>,
+    /// Keyboard register as seen by the last step
+    key: u16,
+}
+
+/// An executed instruction with the state it changed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Step {
+    pc: u16,
+    a: u16,
+    d: u16,
+    /// Address of the written RAM word with the value before and after
+    write: Option<(u16, u16, u16)>,
+}
+
+impl Trace {
+    /// Starts recording at the current cycle of `computer`
+    pub fn new(computer: &Computer, capacity: usize) -> Self {
+        Trace {
+            start: computer.cycles,
+            steps: VecDeque::new(),
+            capacity,
+            keys: Vec::new(),
+            redo: Vec::new(),
+            key: computer.key(),
+        }
+    }
+
+    /// Executes the instruction at PC and records it
+    pub fn step(&mut self, computer: &mut Computer) {
+        while let Some((cycle, _, key)) = self.redo.last().copied() {
+            if cycle > computer.cycles {
+                break;
+            }
+            self.redo.pop();
+            if cycle == computer.cycles {
+                computer.set_key(key);
+            }
+        }
+        let key = computer.key();
+        if key != self.key {
+            self.keys.push((computer.cycles, self.key, key));
+            self.key = key;
+        }
+        let instruction = computer.rom[usize::from(computer.pc)];
+        let address = computer.a & 0x7FFF;
+        let writes = instruction & 0x8008 == 0x8008 && address != KBD;
+        let old = computer.ram[usize::from(address)];
+        let (pc, a, d) = (computer.pc, computer.a, computer.d);
+        computer.step();
+        let write = writes.then(|| (address, old, computer.ram[usize::from(address)]));
+        self.steps.push_back(Step { pc, a, d, write });
+        if self.steps.len() > self.capacity {
+            self.steps.pop_front();
+            self.start += 1;
+        }
+    }
+
+    /// Undoes the last recorded instruction, returns `false` if there is none
+    pub fn step_back(&mut self, computer: &mut Computer) -> bool {
+        let Some(step) = self.steps.pop_back() else {
+            return false;
+        };
+        if let Some((address, old, _)) = step.write {
+            computer.ram[usize::from(address)] = old;
+        }
+        computer.pc = step.pc;
+        computer.a = step.a;
+        computer.d = step.d;
+        computer.cycles -= 1;
+        // Goes back to the key before the change, the next step makes it again
+        while let Some(&change) = self.keys.last().filter(|(c, ..)| *c >= computer.cycles) {
+            computer.set_key(change.1);
+            self.keys.pop();
+            self.redo.push(change);
+        }
+        self.key = computer.key();
+        true
+    }
+
+    /// Like [`Computer::run_with_keys`], recording every instruction
+    pub fn run(&mut self, computer: &mut Computer, max_cycles: u64, timeline: &Timeline) -> Stop {
+        computer.run_split(max_cycles, timeline, |computer, cycles| {
+            for _ in 0..cycles {
+                if computer.is_halted() {
+                    return Stop::Halted;
+                }
+                self.step(computer);
+            }
+            if computer.is_halted() {
+                Stop::Halted
+            } else {
+                Stop::CycleLimit
+            }
+        })
+    }
+
+    /// Number of instructions that can be undone
+    pub fn len(&self) -> usize {
+        self.steps.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.steps.is_empty()
+    }
+
+    /// Cycle of the oldest instruction that can be undone
+    pub fn start(&self) -> u64 {
+        self.start
+    }
+
+    /// The trace in the text format, key changes before the kept
+    /// instructions come first
+    pub fn to_text(&self) -> String {
+        let end = self.start + self.steps.len() as u64;
+        let mut text = format!(
+            "// Hack trace of cycles {} to {}\n",
+            self.start,
+            end.saturating_sub(1)
+        );
+        let mut keys = self.keys.iter().peekable();
+        for (cycle, step) in (self.start..).zip(&self.steps) {
+            while let Some((at, _, key)) = keys.next_if(|(c, ..)| *c <= cycle) {
+                writeln!(text, "{at} key {key}").expect("Writing to a String");
+            }
+            text.push_str(&step_line(cycle, step));
+            text.push('\n');
+        }
+        for (cycle, _, key) in keys {
+            writeln!(text, "{cycle} key {key}").expect("Writing to a String");
+        }
+        text
+    }
+}
+
+fn step_line(cycle: u64, step: &Step) -> String {
+    match step.write {
+        Some((address, _, new)) => format!("{cycle} {} RAM[{address}]={}", step.pc, new as i16),
+        None => format!("{cycle} {}", step.pc),
+    }
+}
+
+/// Runs `computer` from the start with the key changes of a trace in the
+/// text format, checking that it executes the same instructions and writes
+/// the same RAM words. Instructions before the first one of the trace are
+/// executed without checking.
+///
+/// Returns the recording of the replay with the last `capacity` instructions,
+/// or where it first differs from the trace.
+pub fn replay(computer: &mut Computer, text: &str, capacity: usize) -> Result<Trace, String> {
+    let mut keys = Vec::new();
+    let mut steps = Vec::new();
+    for (i, line) in text.lines().enumerate() {
+        let line = line.split("//").next().unwrap_or_default().trim();
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let cycle = |word: &str| {
+            word.parse::<u64>()
+                .map_err(|_| format!("line {}: bad cycle `{word}`", i + 1))
+        };
+        match words[..] {
+            [] => {}
+            [c, "key", key] => {
+                let key = key
+                    .parse::<u16>()
+                    .map_err(|_| format!("line {}: bad key code `{key}`", i + 1))?;
+                keys.push((cycle(c)?, key));
+            }
+            [c, _] | [c, _, _] => steps.push((i + 1, cycle(c)?, words.join(" "))),
+            _ => {
+                return Err(format!(
+                    "line {}: expected `CYCLE PC [RAM[ADDRESS]=VALUE]` or `CYCLE key CODE`",
+                    i + 1
+                ))
+            }
+        }
+    }
+    let timeline = Timeline::from_events(keys);
+    // Keeps at least the step to compare
+    let mut trace = Trace::new(computer, capacity.max(1));
+    for (number, cycle, expected) in steps {
+        if cycle < computer.cycles {
+            return Err(format!("line {number}: cycle {cycle} is out of order"));
+        }
+        while computer.cycles <= cycle {
+            if let Some(key) = timeline.key_at(computer.cycles) {
+                computer.set_key(key);
+            }
+            if computer.is_halted() {
+                return Err(format!(
+                    "line {number}: the program halted after {} cycles",
+                    computer.cycles
+                ));
+            }
+            trace.step(computer);
+        }
+        let last = trace.steps.back().expect("Just recorded");
+        let actual = step_line(computer.cycles - 1, last);
+        if actual != expected {
+            return Err(format!(
+                "line {number}: expected `{expected}`, the replay executed `{actual}`"
+            ));
+        }
+    }
+    Ok(trace)
+}
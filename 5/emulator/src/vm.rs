@@ -0,0 +1,355 @@
+use crate::computer::RAM_SIZE;
+
+use std::{collections::HashMap, fs, path::Path};
+
+const SP: usize = 0;
+const LCL: usize = 1;
+const ARG: usize = 2;
+const THIS: usize = 3;
+const THAT: usize = 4;
+const TEMP: u16 = 5;
+const STATIC: u16 = 16;
+
+/// Runs VM code directly, one VM command per step.
+///
+/// Frames and segments live in the RAM exactly like in translated code, only
+/// return addresses are indices of VM commands instead of ROM addresses.
+#[derive(Debug, Clone)]
+pub struct VmEmulator {
+    pub ram: Box<[u16; RAM_SIZE]>,
+    commands: Vec<Command>,
+    /// Index of the next command
+    pub pc: usize,
+    /// Commands executed so far
+    pub steps: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Command {
+    Arithmetic(Operation),
+    Push(Segment, u16),
+    Pop(Segment, u16),
+    /// Jump targets are resolved to command indices while loading
+    Goto(usize),
+    IfGoto(usize),
+    Function {
+        name: String,
+        locals: u16,
+    },
+    Call {
+        function: usize,
+        args: u16,
+    },
+    Return,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Operation {
+    Add,
+    Sub,
+    Neg,
+    Eq,
+    Gt,
+    Lt,
+    And,
+    Or,
+    Not,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Segment {
+    Argument,
+    Local,
+    /// Base address of the statics of the file the command is in
+    Static(u16),
+    Constant,
+    This,
+    That,
+    Pointer,
+    Temp,
+}
+
+/// Command with its jump targets still given as names. Labels don't take a
+/// step, they refer to the command after them.
+enum Parsed {
+    Ready(Command),
+    Label(String),
+    Goto(String),
+    IfGoto(String),
+    Call(String, u16),
+}
+
+impl VmEmulator {
+    /// Loads a `*.vm` file, or every `*.vm` file of a directory in
+    /// alphabetical order. Starts with `Sys.init` if there is one, like the
+    /// official VM emulator.
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let files = if path.is_dir() {
+            let mut files: Vec<_> = fs::read_dir(path)
+                .map_err(|e| format!("couldn't read `{}`: {e}", path.display()))?
+                .filter_map(|entry| Some(entry.ok()?.path()))
+                .filter(|f| f.extension().is_some_and(|e| e == "vm"))
+                .collect();
+            files.sort();
+            files
+        } else {
+            vec![path.to_owned()]
+        };
+        if files.is_empty() {
+            return Err(format!("no `*.vm` files in `{}`", path.display()));
+        }
+        let sources = files
+            .iter()
+            .map(|f| {
+                let source = fs::read_to_string(f)
+                    .map_err(|e| format!("couldn't read `{}`: {e}", f.display()))?;
+                Ok((f.display().to_string(), source))
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+        Self::from_sources(&sources)
+    }
+
+    /// `sources` are pairs of file name and VM code
+    pub fn from_sources(sources: &[(String, String)]) -> Result<Self, String> {
+        let mut parsed = Vec::new();
+        // Labels are local to the function they are defined in
+        let mut labels = HashMap::new();
+        let mut functions = HashMap::new();
+        let mut function = String::new();
+        let mut static_base = STATIC;
+        for (file, source) in sources {
+            let mut statics = 0;
+            for (i, line) in source.lines().enumerate() {
+                let text = line.split("//").next().unwrap_or_default().trim();
+                if text.is_empty() {
+                    continue;
+                }
+                let command = parse_command(text, static_base)
+                    .map_err(|e| format!("{file}:{}: {e}", i + 1))?;
+                if let Parsed::Ready(Command::Push(Segment::Static(_), index))
+                | Parsed::Ready(Command::Pop(Segment::Static(_), index)) = command
+                {
+                    // The parser checked that the address and the one after
+                    // it fit in a word
+                    statics = statics.max(index + 1);
+                }
+                if let Parsed::Label(label) = command {
+                    labels.insert((function.clone(), label), parsed.len());
+                    continue;
+                }
+                if let Parsed::Ready(Command::Function { name, .. }) = &command {
+                    function = name.clone();
+                    functions.insert(name.clone(), parsed.len());
+                }
+                parsed.push((file, i + 1, command));
+            }
+            static_base += statics;
+        }
+
+        let mut commands = Vec::new();
+        let mut function = String::new();
+        for (file, line, command) in &parsed {
+            let target = |label: &String| {
+                labels
+                    .get(&(function.clone(), label.clone()))
+                    .copied()
+                    .ok_or_else(|| format!("{file}:{line}: unknown label `{label}`"))
+            };
+            commands.push(match command {
+                Parsed::Ready(command) => {
+                    if let Command::Function { name, .. } = command {
+                        function = name.clone();
+                    }
+                    command.clone()
+                }
+                Parsed::Label(_) => unreachable!("Labels are removed while parsing"),
+                Parsed::Goto(label) => Command::Goto(target(label)?),
+                Parsed::IfGoto(label) => Command::IfGoto(target(label)?),
+                Parsed::Call(name, args) => Command::Call {
+                    function: *functions
+                        .get(name)
+                        .ok_or_else(|| format!("{file}:{line}: unknown function `{name}`"))?,
+                    args: *args,
+                },
+            });
+        }
+        Ok(VmEmulator {
+            ram: vec![0; RAM_SIZE]
+                .into_boxed_slice()
+                .try_into()
+                .expect("Length is RAM_SIZE"),
+            pc: functions.get("Sys.init").copied().unwrap_or(0),
+            commands,
+            steps: 0,
+        })
+    }
+
+    /// Executes the command at `pc`, doing nothing after the last command
+    pub fn step(&mut self) {
+        let Some(command) = self.commands.get(self.pc) else {
+            return;
+        };
+        self.steps += 1;
+        self.pc += 1;
+        match *command {
+            Command::Arithmetic(operation) => self.arithmetic(operation),
+            Command::Push(segment, index) => {
+                let value = match segment {
+                    Segment::Constant => index,
+                    _ => self.ram[self.address(segment, index)],
+                };
+                self.push(value);
+            }
+            Command::Pop(segment, index) => {
+                let value = self.pop();
+                let address = self.address(segment, index);
+                self.ram[address] = value;
+            }
+            Command::Goto(target) => self.pc = target,
+            Command::IfGoto(target) => {
+                if self.pop() != 0 {
+                    self.pc = target;
+                }
+            }
+            Command::Function { locals, .. } => {
+                for _ in 0..locals {
+                    self.push(0);
+                }
+            }
+            Command::Call { function, args } => {
+                self.push(self.pc as u16);
+                for pointer in [LCL, ARG, THIS, THAT] {
+                    self.push(self.ram[pointer]);
+                }
+                let sp = self.ram[SP];
+                self.ram[ARG] = sp.wrapping_sub(args + 5);
+                self.ram[LCL] = sp;
+                self.pc = function;
+            }
+            Command::Return => {
+                let frame = usize::from(self.ram[LCL]);
+                let return_address = self.ram[(frame.wrapping_sub(5)) % RAM_SIZE];
+                let result = self.pop();
+                let arg = self.ram[ARG];
+                self.ram[usize::from(arg) % RAM_SIZE] = result;
+                self.ram[SP] = arg.wrapping_add(1);
+                for (offset, pointer) in [THAT, THIS, ARG, LCL].into_iter().enumerate() {
+                    self.ram[pointer] = self.ram[(frame.wrapping_sub(offset + 1)) % RAM_SIZE];
+                }
+                self.pc = usize::from(return_address);
+            }
+        }
+    }
+
+    fn arithmetic(&mut self, operation: Operation) {
+        let y = self.pop();
+        let result = match operation {
+            Operation::Neg => y.wrapping_neg(),
+            Operation::Not => !y,
+            _ => {
+                let x = self.pop();
+                let flag = |b: bool| if b { 0xFFFF } else { 0 };
+                match operation {
+                    Operation::Add => x.wrapping_add(y),
+                    Operation::Sub => x.wrapping_sub(y),
+                    Operation::Eq => flag(x == y),
+                    Operation::Gt => flag((x as i16) > (y as i16)),
+                    Operation::Lt => flag((x as i16) < (y as i16)),
+                    Operation::And => x & y,
+                    Operation::Or => x | y,
+                    Operation::Neg | Operation::Not => unreachable!("Unary operations"),
+                }
+            }
+        };
+        self.push(result);
+    }
+
+    /// RAM address of `segment[index]`, not for `constant`
+    fn address(&self, segment: Segment, index: u16) -> usize {
+        let address = match segment {
+            Segment::Argument => self.ram[ARG].wrapping_add(index),
+            Segment::Local => self.ram[LCL].wrapping_add(index),
+            Segment::This => self.ram[THIS].wrapping_add(index),
+            Segment::That => self.ram[THAT].wrapping_add(index),
+            // Checked while parsing
+            Segment::Static(base) => base + index,
+            Segment::Pointer => THIS as u16 + index,
+            Segment::Temp => TEMP + index,
+            Segment::Constant => unreachable!("Constants have no address"),
+        };
+        usize::from(address) % RAM_SIZE
+    }
+
+    fn push(&mut self, value: u16) {
+        let sp = self.ram[SP];
+        self.ram[usize::from(sp) % RAM_SIZE] = value;
+        self.ram[SP] = sp.wrapping_add(1);
+    }
+
+    fn pop(&mut self) -> u16 {
+        let sp = self.ram[SP].wrapping_sub(1);
+        self.ram[SP] = sp;
+        self.ram[usize::from(sp) % RAM_SIZE]
+    }
+}
+
+fn parse_command(text: &str, static_base: u16) -> Result<Parsed, String> {
+    let words: Vec<&str> = text.split_whitespace().collect();
+    let number = |word: &str| {
+        word.parse::<u16>()
+            .map_err(|_| format!("bad number `{word}`"))
+    };
+    Ok(match words[..] {
+        [operation] => Parsed::Ready(match operation {
+            "add" => Command::Arithmetic(Operation::Add),
+            "sub" => Command::Arithmetic(Operation::Sub),
+            "neg" => Command::Arithmetic(Operation::Neg),
+            "eq" => Command::Arithmetic(Operation::Eq),
+            "gt" => Command::Arithmetic(Operation::Gt),
+            "lt" => Command::Arithmetic(Operation::Lt),
+            "and" => Command::Arithmetic(Operation::And),
+            "or" => Command::Arithmetic(Operation::Or),
+            "not" => Command::Arithmetic(Operation::Not),
+            "return" => Command::Return,
+            _ => return Err(format!("unknown command `{text}`")),
+        }),
+        ["push" | "pop", segment, index] => {
+            let index = number(index)?;
+            let segment = match segment {
+                "argument" => Segment::Argument,
+                "local" => Segment::Local,
+                "static" => match static_base.checked_add(index) {
+                    Some(address) if address < u16::MAX => Segment::Static(static_base),
+                    _ => return Err(format!("static index out of range in `{text}`")),
+                },
+                "constant" if words[0] == "push" => Segment::Constant,
+                "this" => Segment::This,
+                "that" => Segment::That,
+                "pointer" if index < 2 => Segment::Pointer,
+                "temp" if index < 8 => Segment::Temp,
+                _ => return Err(format!("bad segment in `{text}`")),
+            };
+            Parsed::Ready(if words[0] == "push" {
+                Command::Push(segment, index)
+            } else {
+                Command::Pop(segment, index)
+            })
+        }
+        ["label", label] => Parsed::Label(label.to_owned()),
+        ["goto", label] => Parsed::Goto(label.to_owned()),
+        ["if-goto", label] => Parsed::IfGoto(label.to_owned()),
+        ["function", name, locals] => Parsed::Ready(Command::Function {
+            name: name.to_owned(),
+            locals: number(locals)?,
+        }),
+        ["call", name, args] => {
+            let args = number(args)?;
+            // The frame of 5 words is below the arguments
+            if args.checked_add(5).is_none() {
+                return Err(format!("too many arguments in `{text}`"));
+            }
+            Parsed::Call(name.to_owned(), args)
+        }
+        _ => return Err(format!("unknown command `{text}`")),
+    })
+}
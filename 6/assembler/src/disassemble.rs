@@ -0,0 +1,83 @@
+use crate::{
+    error::{AsmError, ErrorKind},
+    instruction::{AValue, Instruction, Jump},
+    parser::trimmed_lines,
+};
+
+use std::{collections::BTreeSet, path::Path};
+
+/// Reads the textual `*.hack` format, one `0`/`1` string per word.
+pub fn parse_hack(file: &Path, source: &str) -> Result<Vec<u16>, Vec<AsmError>> {
+    let mut errors = Vec::new();
+    let words = trimmed_lines(source)
+        .filter_map(|l| {
+            let word = (l.text.len() == 16)
+                .then(|| u16::from_str_radix(l.text, 2).ok())
+                .flatten();
+            if word.is_none() {
+                let kind = ErrorKind::BadLiteral(l.text.to_owned());
+                errors.push(l.error(file, 0..l.text.len(), kind));
+            }
+            word
+        })
+        .collect();
+    if errors.is_empty() {
+        Ok(words)
+    } else {
+        Err(errors)
+    }
+}
+
+/// Turns the textual `*.hack` format back into assembly.
+///
+/// Targets of jumps get a synthesized `(L{address})` label and well-known
+/// addresses are annotated with a comment, so that assembling the result
+/// yields the original words again. Words that are no instruction, like data
+/// or C-instructions with unused bits, are kept as `// .word` comments, which
+/// don't assemble.
+pub fn disassemble(file: &Path, source: &str) -> Result<String, Vec<AsmError>> {
+    let words = parse_hack(file, source)?;
+    let program: Vec<Option<Instruction>> = words.iter().map(|w| Instruction::decode(*w)).collect();
+
+    let is_jump = |i: &Option<Instruction>| matches!(i, Some(Instruction::C { jump, .. }) if *jump != Jump::Null);
+    let followed_by_jump = |addr: usize| program.get(addr + 1).is_some_and(is_jump);
+    let jump_targets: BTreeSet<u16> = program
+        .iter()
+        .enumerate()
+        .filter_map(|(addr, instruction)| match instruction {
+            Some(Instruction::A(AValue::Literal(value)))
+                if followed_by_jump(addr) && usize::from(*value) < program.len() =>
+            {
+                Some(*value)
+            }
+            _ => None,
+        })
+        .collect();
+    let accesses_memory = |i: &Option<Instruction>| matches!(i, Some(Instruction::C { dest, comp, .. }) if dest.writes_m() || comp.reads_m());
+
+    let mut result = String::new();
+    for (addr, instruction) in program.iter().enumerate() {
+        if jump_targets.contains(&(addr as u16)) {
+            result += &format!("(L{addr})\n");
+        }
+        match instruction {
+            Some(Instruction::A(AValue::Literal(value)))
+                if followed_by_jump(addr) && jump_targets.contains(value) =>
+            {
+                result += &format!("  @L{value}\n");
+            }
+            Some(Instruction::A(AValue::Literal(value))) => {
+                let uses_address = program.get(addr + 1).is_some_and(accesses_memory);
+                match value {
+                    16384 => result += "  @16384 // SCREEN\n",
+                    24576 => result += "  @24576 // KBD\n",
+                    0..=15 if uses_address => result += &format!("  @{value} // R{value}\n"),
+                    _ => result += &format!("  @{value}\n"),
+                }
+            }
+            Some(instruction) => result += &format!("  {instruction}\n"),
+            None => result += &format!("  // .word {:#06x}\n", words[addr]),
+        }
+    }
+    Ok(result)
+}
@@ -0,0 +1,122 @@
+use std::{
+    fmt::{self, Display},
+    ops::Range,
+    path::PathBuf,
+};
+
+/// A problem found while assembling, pointing back at the offending source.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AsmError {
+    pub file: PathBuf,
+    /// `None` if the error is not tied to a line, e.g. an unreadable file
+    pub location: Option<Location>,
+    pub kind: ErrorKind,
+}
+
+/// Position inside a source file, both 1-based.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Location {
+    pub line: usize,
+    /// Character columns covered by the error, end is exclusive
+    pub columns: Range<usize>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ErrorKind {
+    UnknownMnemonic {
+        field: Field,
+        text: String,
+    },
+    BadLiteral(String),
+    /// A-instruction value that doesn't fit into 15 bits
+    OutOfRange(i64),
+    UnclosedLabel(String),
+    DuplicateLabel {
+        name: String,
+        first_line: usize,
+    },
+    RedefinedSymbol(String),
+    BadDefinition(String),
+    BadDirective(String),
+    BadMacro(String),
+    IncludeCycle(String),
+    /// Program with the given number of words doesn't fit into the ROM
+    RomOverflow(usize),
+    /// Variable that would be allocated inside the screen memory map
+    RamOverflow(String),
+    Io(String),
+}
+
+/// The part of a C-instruction an unknown mnemonic was found in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Field {
+    Dest,
+    Comp,
+    Jump,
+}
+
+impl Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:", self.file.display())?;
+        if let Some(Location { line, columns }) = &self.location {
+            write!(f, "{line}:{}:", columns.start)?;
+        }
+        write!(f, " error: {}", self.kind)
+    }
+}
+
+impl Display for ErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ErrorKind::UnknownMnemonic { field, text } => {
+                write!(f, "unknown {field} mnemonic `{text}`")
+            }
+            ErrorKind::BadLiteral(text) => write!(f, "bad literal `{text}`"),
+            ErrorKind::OutOfRange(value) => {
+                write!(
+                    f,
+                    "value {value} doesn't fit into the 15 bits of an A-instruction"
+                )
+            }
+            ErrorKind::UnclosedLabel(text) => {
+                write!(f, "missing closing bracket in label `{text}`")
+            }
+            ErrorKind::DuplicateLabel { name, first_line } => {
+                write!(f, "label `{name}` already defined on line {first_line}")
+            }
+            ErrorKind::RedefinedSymbol(name) => {
+                write!(f, "`{name}` redefines a predefined symbol")
+            }
+            ErrorKind::BadDefinition(text) => {
+                write!(f, "expected `NAME=VALUE`, got `{text}`")
+            }
+            ErrorKind::BadDirective(text) => write!(f, "bad directive `{text}`"),
+            ErrorKind::BadMacro(message) => f.write_str(message),
+            ErrorKind::IncludeCycle(name) => {
+                write!(f, "`{name}` is already being included")
+            }
+            ErrorKind::RomOverflow(words) => {
+                write!(f, "program has {words} words, the ROM only fits 32768")
+            }
+            ErrorKind::RamOverflow(name) => {
+                write!(
+                    f,
+                    "variable `{name}` doesn't fit below the screen memory map at 16384"
+                )
+            }
+            ErrorKind::Io(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl Display for Field {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Field::Dest => "dest",
+            Field::Comp => "comp",
+            Field::Jump => "jump",
+        })
+    }
+}
+
+impl std::error::Error for AsmError {}
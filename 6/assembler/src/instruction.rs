@@ -0,0 +1,356 @@
+use crate::{error::ErrorKind, symbols::SymbolTable};
+
+use std::{
+    fmt::{self, Display},
+    str::FromStr,
+};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Instruction {
+    /// `@value`
+    A(AValue),
+    /// `dest=comp;jump`
+    C { dest: Dest, comp: Comp, jump: Jump },
+    /// `(name)`, doesn't occupy a ROM word
+    Label(String),
+}
+
+/// Largest value an A-instruction can load, it only has 15 bits
+pub const MAX_A_VALUE: u16 = 0x7FFF;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AValue {
+    Literal(u16),
+    Symbol(String),
+    /// Constant expression like `SCREEN+32`, evaluated after label resolution
+    Sum(Box<AValue>, Operator, Box<AValue>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operator {
+    Plus,
+    Minus,
+}
+
+/// Bit values are the `d1 d2 d3` bits of a C-instruction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Dest {
+    Null = 0b000,
+    M = 0b001,
+    D = 0b010,
+    DM = 0b011,
+    A = 0b100,
+    AM = 0b101,
+    AD = 0b110,
+    ADM = 0b111,
+}
+
+/// Bit values are the `j1 j2 j3` bits of a C-instruction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Jump {
+    Null = 0b000,
+    JGT = 0b001,
+    JEQ = 0b010,
+    JGE = 0b011,
+    JLT = 0b100,
+    JNE = 0b101,
+    JLE = 0b110,
+    JMP = 0b111,
+}
+
+/// Bit values are the `a c1 c2 c3 c4 c5 c6` bits of a C-instruction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Comp {
+    Zero = 0b0101010,
+    One = 0b0111111,
+    MinusOne = 0b0111010,
+    D = 0b0001100,
+    A = 0b0110000,
+    NotD = 0b0001101,
+    NotA = 0b0110001,
+    NegD = 0b0001111,
+    NegA = 0b0110011,
+    DPlusOne = 0b0011111,
+    APlusOne = 0b0110111,
+    DMinusOne = 0b0001110,
+    AMinusOne = 0b0110010,
+    DPlusA = 0b0000010,
+    DMinusA = 0b0010011,
+    AMinusD = 0b0000111,
+    DAndA = 0b0000000,
+    DOrA = 0b0010101,
+    M = 0b1110000,
+    NotM = 0b1110001,
+    NegM = 0b1110011,
+    MPlusOne = 0b1110111,
+    MMinusOne = 0b1110010,
+    DPlusM = 0b1000010,
+    DMinusM = 0b1010011,
+    MMinusD = 0b1000111,
+    DAndM = 0b1000000,
+    DOrM = 0b1010101,
+}
+
+// Where several mnemonics map to the same value, the first entry is the one
+// used for printing.
+
+const DEST: [(&str, Dest); 9] = [
+    ("", Dest::Null),
+    ("M", Dest::M),
+    ("D", Dest::D),
+    ("DM", Dest::DM),
+    ("MD", Dest::DM), // MD is used in PongL.asm
+    ("A", Dest::A),
+    ("AM", Dest::AM),
+    ("AD", Dest::AD),
+    ("ADM", Dest::ADM),
+];
+
+const JUMP: [(&str, Jump); 8] = [
+    ("", Jump::Null),
+    ("JGT", Jump::JGT),
+    ("JEQ", Jump::JEQ),
+    ("JGE", Jump::JGE),
+    ("JLT", Jump::JLT),
+    ("JNE", Jump::JNE),
+    ("JLE", Jump::JLE),
+    ("JMP", Jump::JMP),
+];
+
+const COMP: [(&str, Comp); 34] = [
+    ("0", Comp::Zero),
+    ("1", Comp::One),
+    ("-1", Comp::MinusOne),
+    ("D", Comp::D),
+    ("A", Comp::A),
+    ("!D", Comp::NotD),
+    ("!A", Comp::NotA),
+    ("-D", Comp::NegD),
+    ("-A", Comp::NegA),
+    ("D+1", Comp::DPlusOne),
+    ("A+1", Comp::APlusOne),
+    ("D-1", Comp::DMinusOne),
+    ("A-1", Comp::AMinusOne),
+    ("D+A", Comp::DPlusA),
+    ("D-A", Comp::DMinusA),
+    ("A-D", Comp::AMinusD),
+    ("D&A", Comp::DAndA),
+    ("D|A", Comp::DOrA),
+    ("M", Comp::M),
+    ("!M", Comp::NotM),
+    ("-M", Comp::NegM),
+    ("M+1", Comp::MPlusOne),
+    ("M-1", Comp::MMinusOne),
+    ("D+M", Comp::DPlusM),
+    ("D-M", Comp::DMinusM),
+    ("M-D", Comp::MMinusD),
+    ("D&M", Comp::DAndM),
+    ("D|M", Comp::DOrM),
+    // Swapped operands are written by the VM translator, e.g. `M=M+D`
+    ("A+D", Comp::DPlusA),
+    ("M+D", Comp::DPlusM),
+    ("A&D", Comp::DAndA),
+    ("M&D", Comp::DAndM),
+    ("A|D", Comp::DOrA),
+    ("M|D", Comp::DOrM),
+];
+
+impl Instruction {
+    /// Machine word of this instruction, `None` for labels.
+    ///
+    /// Symbols that are not yet in `symbols` are allocated as variables.
+    pub fn encode(&self, symbols: &mut SymbolTable) -> Result<Option<u16>, ErrorKind> {
+        Ok(match self {
+            Instruction::A(value) => {
+                let value = value.evaluate(symbols)?;
+                match u16::try_from(value) {
+                    Ok(word) if word <= MAX_A_VALUE => Some(word),
+                    _ => return Err(ErrorKind::OutOfRange(value)),
+                }
+            }
+            Instruction::C { dest, comp, jump } => {
+                Some(0b111 << 13 | (*comp as u16) << 6 | (*dest as u16) << 3 | *jump as u16)
+            }
+            Instruction::Label(_) => None,
+        })
+    }
+
+    /// Inverse of [`Instruction::encode`], `None` if `word` is no valid
+    /// instruction.
+    pub fn decode(word: u16) -> Option<Self> {
+        if word & 0x8000 == 0 {
+            return Some(Instruction::A(AValue::Literal(word)));
+        }
+        if word & 0x6000 != 0x6000 {
+            return None;
+        }
+        Some(Instruction::C {
+            dest: by_value(&DEST, (word >> 3) & 0b111)?,
+            comp: by_value(&COMP, (word >> 6) & 0b111_1111)?,
+            jump: by_value(&JUMP, word & 0b111)?,
+        })
+    }
+}
+
+impl AValue {
+    /// Symbols that are not yet in `symbols` are allocated as variables,
+    /// which fails once the address space is used up.
+    pub fn evaluate(&self, symbols: &mut SymbolTable) -> Result<i64, ErrorKind> {
+        Ok(match self {
+            AValue::Literal(value) => (*value).into(),
+            AValue::Symbol(name) => symbols.resolve(name)?.into(),
+            AValue::Sum(lhs, Operator::Plus, rhs) => {
+                lhs.evaluate(symbols)? + rhs.evaluate(symbols)?
+            }
+            AValue::Sum(lhs, Operator::Minus, rhs) => {
+                lhs.evaluate(symbols)? - rhs.evaluate(symbols)?
+            }
+        })
+    }
+
+    /// Like [`AValue::evaluate`], but `None` for symbols not in `symbols`
+    pub fn value(&self, symbols: &SymbolTable) -> Option<i64> {
+        Some(match self {
+            AValue::Literal(value) => (*value).into(),
+            AValue::Symbol(name) => symbols.get(name)?.into(),
+            AValue::Sum(lhs, Operator::Plus, rhs) => lhs.value(symbols)? + rhs.value(symbols)?,
+            AValue::Sum(lhs, Operator::Minus, rhs) => lhs.value(symbols)? - rhs.value(symbols)?,
+        })
+    }
+}
+
+impl Dest {
+    pub fn writes_m(self) -> bool {
+        self as u16 & 0b001 != 0
+    }
+
+    pub fn writes_d(self) -> bool {
+        self as u16 & 0b010 != 0
+    }
+
+    pub fn writes_a(self) -> bool {
+        self as u16 & 0b100 != 0
+    }
+}
+
+impl Comp {
+    /// Whether the value is computed from `M` instead of `A`
+    pub fn reads_m(self) -> bool {
+        self as u16 & 0b100_0000 != 0
+    }
+}
+
+impl Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Instruction::A(value) => write!(f, "@{value}"),
+            Instruction::C { dest, comp, jump } => {
+                if *dest != Dest::Null {
+                    write!(f, "{dest}=")?;
+                }
+                write!(f, "{comp}")?;
+                if *jump != Jump::Null {
+                    write!(f, ";{jump}")?;
+                }
+                Ok(())
+            }
+            Instruction::Label(name) => write!(f, "({name})"),
+        }
+    }
+}
+
+impl Display for AValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AValue::Literal(value) => write!(f, "{value}"),
+            AValue::Symbol(name) => f.write_str(name),
+            AValue::Sum(lhs, Operator::Plus, rhs) => write!(f, "{lhs}+{rhs}"),
+            AValue::Sum(lhs, Operator::Minus, rhs) => write!(f, "{lhs}-{rhs}"),
+        }
+    }
+}
+
+impl Display for Dest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(mnemonic(&DEST, *self))
+    }
+}
+
+impl Display for Jump {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(mnemonic(&JUMP, *self))
+    }
+}
+
+impl Display for Comp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(mnemonic(&COMP, *self))
+    }
+}
+
+impl FromStr for Dest {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        by_mnemonic(&DEST, s)
+    }
+}
+
+impl FromStr for Jump {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        by_mnemonic(&JUMP, s)
+    }
+}
+
+impl FromStr for Comp {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        by_mnemonic(&COMP, s)
+    }
+}
+
+/// The empty mnemonic of `Dest::Null` and `Jump::Null` is only used for
+/// printing, it has to be left out when writing a C-instruction.
+fn by_mnemonic<T: Copy>(table: &[(&str, T)], mnemonic: &str) -> Result<T, ()> {
+    table
+        .iter()
+        .find(|(m, _)| !m.is_empty() && *m == mnemonic)
+        .map(|(_, v)| *v)
+        .ok_or(())
+}
+
+fn by_value<T: Copy + Into<u16>>(table: &[(&str, T)], bits: u16) -> Option<T> {
+    table
+        .iter()
+        .find(|(_, v)| (*v).into() == bits)
+        .map(|(_, v)| *v)
+}
+
+fn mnemonic<T: PartialEq>(table: &[(&'static str, T)], value: T) -> &'static str {
+    table
+        .iter()
+        .find(|(_, v)| *v == value)
+        .map(|(m, _)| *m)
+        .expect("Every value has a mnemonic")
+}
+
+impl From<Dest> for u16 {
+    fn from(value: Dest) -> Self {
+        value as u16
+    }
+}
+
+impl From<Jump> for u16 {
+    fn from(value: Jump) -> Self {
+        value as u16
+    }
+}
+
+impl From<Comp> for u16 {
+    fn from(value: Comp) -> Self {
+        value as u16
+    }
+}
@@ -0,0 +1,621 @@
+//! Assembler for the Hack machine language.
+//!
+//! Source is turned into [`Statement`]s by [`parse`], labels are collected by
+//! [`SymbolTable::first_pass`] and [`Instruction::encode`] produces the
+//! machine words, allocating variables on the way.
+
+mod disassemble;
+mod error;
+mod instruction;
+mod lint;
+mod listing;
+mod macros;
+mod output;
+mod parser;
+mod source_map;
+mod symbols;
+
+pub use disassemble::{disassemble, parse_hack};
+pub use error::{AsmError, ErrorKind, Field, Location};
+pub use instruction::{AValue, Comp, Dest, Instruction, Jump, Operator, MAX_A_VALUE};
+pub use lint::{lint, Warning, WarningKind};
+pub use listing::{listing, size_report, symbol_map};
+pub use output::{OutputFormat, ROM_SIZE};
+pub use parser::{parse, Statement};
+pub use source_map::{source_map, SourceMapFormat};
+pub use symbols::{Symbol, SymbolKind, SymbolTable};
+
+use symbols::SCREEN;
+
+use std::{fs, path::Path};
+
+pub fn assemble(asm_file: &Path) -> Result<Vec<u16>, Vec<AsmError>> {
+    assemble_source(asm_file, &read_source(asm_file)?)
+}
+
+pub fn assemble_source(file: &Path, source: &str) -> Result<Vec<u16>, Vec<AsmError>> {
+    assemble_with(file, source, SymbolTable::predefined())
+}
+
+pub fn assemble_with(
+    file: &Path,
+    source: &str,
+    symbols: SymbolTable,
+) -> Result<Vec<u16>, Vec<AsmError>> {
+    assemble_program(file, source, symbols).map(|p| p.words)
+}
+
+/// Assembles `source`, reporting every error found instead of stopping at
+/// the first one. `file` is only used to label the errors and `symbols` holds
+/// the predefined symbols.
+pub fn assemble_program(
+    file: &Path,
+    source: &str,
+    mut symbols: SymbolTable,
+) -> Result<Program, Vec<AsmError>> {
+    let mut errors = Vec::new();
+    let statements = parser::parse_lines(file, source, &mut errors);
+    symbols.add_labels(&statements, &mut errors);
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+    let mut words = Vec::new();
+    for s in &statements {
+        let error = |kind| AsmError {
+            file: s.file.clone(),
+            location: Some(s.location.clone()),
+            kind,
+        };
+        let allocated = symbols.last_variable_address();
+        match s.instruction.encode(&mut symbols) {
+            Ok(word) => words.extend(word),
+            Err(kind) => errors.push(error(kind)),
+        }
+        // Only the first variable that runs into the screen is reported
+        if allocated < SCREEN && symbols.last_variable_address() >= SCREEN {
+            let name = symbols
+                .iter()
+                .find(|(_, v)| v.kind == SymbolKind::Variable && v.address == SCREEN)
+                .map(|(name, _)| name.to_owned())
+                .unwrap_or_default();
+            errors.push(error(ErrorKind::RamOverflow(name)));
+        }
+    }
+    // Point at the first instruction that doesn't fit
+    let overflow = statements
+        .iter()
+        .filter(|s| !matches!(s.instruction, Instruction::Label(_)))
+        .nth(ROM_SIZE);
+    if let Some(s) = overflow {
+        errors.push(AsmError {
+            file: s.file.clone(),
+            location: Some(s.location.clone()),
+            kind: ErrorKind::RomOverflow(words.len()),
+        });
+    }
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+    Ok(Program {
+        statements,
+        words,
+        symbols,
+    })
+}
+
+/// An assembled source file
+#[derive(Debug, Clone)]
+pub struct Program {
+    pub statements: Vec<Statement>,
+    /// One word for every statement that isn't a label
+    pub words: Vec<u16>,
+    /// Predefined symbols, labels and the allocated variables
+    pub symbols: SymbolTable,
+}
+
+impl Program {
+    /// Each statement with its ROM address and word. Labels have the address
+    /// of the next instruction and no word.
+    pub fn rom(&self) -> impl Iterator<Item = (u16, Option<u16>, &Statement)> {
+        let mut address = 0;
+        self.statements.iter().map(move |s| {
+            if let Instruction::Label(_) = s.instruction {
+                (address, None, s)
+            } else {
+                address += 1;
+                (address - 1, Some(self.words[usize::from(address - 1)]), s)
+            }
+        })
+    }
+}
+
+/// The textual `*.hack` format, one `0`/`1` string per word
+pub fn to_hack(words: &[u16]) -> String {
+    words.iter().map(|w| format!("{w:016b}\n")).collect()
+}
+
+pub fn read_source(file: &Path) -> Result<String, Vec<AsmError>> {
+    fs::read_to_string(file).map_err(|e| {
+        vec![AsmError {
+            file: file.to_owned(),
+            location: None,
+            kind: ErrorKind::Io(format!("couldn't read file: {e}")),
+        }]
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::path::PathBuf;
+
+    #[test]
+    fn add() {
+        test_assemble("../add/Add.asm");
+    }
+
+    #[test]
+    fn max_l() {
+        test_assemble("../max/MaxL.asm");
+    }
+
+    #[test]
+    fn pong_l() {
+        test_assemble("../pong/PongL.asm");
+    }
+
+    #[test]
+    fn max() {
+        test_assemble("../max/Max.asm");
+    }
+
+    #[test]
+    fn pong() {
+        test_assemble("../pong/Pong.asm");
+    }
+
+    #[test]
+    fn macros() {
+        test_assemble("../macros/Macros.asm");
+    }
+
+    #[test]
+    fn reports_every_error_with_location() {
+        let source = "@1\n  AM=D+X // typo\nD;JXX\n(LOOP\n@1x\n(END)\n(END)\nMX=D\n";
+        let errors = assemble_source(Path::new("bad.asm"), source).unwrap_err();
+        let found: Vec<_> = errors
+            .iter()
+            .map(|e| {
+                let l = e.location.clone().unwrap();
+                (l.line, l.columns, e.kind.clone())
+            })
+            .collect();
+        let unknown = |field, text: &str| ErrorKind::UnknownMnemonic {
+            field,
+            text: text.to_owned(),
+        };
+        assert_eq!(
+            found,
+            [
+                (2, 6..9, unknown(Field::Comp, "D+X")),
+                (3, 3..6, unknown(Field::Jump, "JXX")),
+                (4, 1..6, ErrorKind::UnclosedLabel("(LOOP".to_owned())),
+                (5, 2..4, ErrorKind::BadLiteral("1x".to_owned())),
+                (8, 1..3, unknown(Field::Dest, "MX")),
+                (
+                    7,
+                    2..5,
+                    ErrorKind::DuplicateLabel {
+                        name: "END".to_owned(),
+                        first_line: 6
+                    }
+                ),
+            ]
+        );
+        assert_eq!(
+            errors[0].to_string(),
+            "bad.asm:2:6: error: unknown comp mnemonic `D+X`"
+        );
+    }
+
+    #[test]
+    fn parse_typed_instructions() {
+        let statements = parse(Path::new("in.asm"), "(START)\n@R0\nMD=M+1;JNE\n@7\n").unwrap();
+        let instructions: Vec<_> = statements.into_iter().map(|s| s.instruction).collect();
+        assert_eq!(
+            instructions,
+            [
+                Instruction::Label("START".to_owned()),
+                Instruction::A(AValue::Symbol("R0".to_owned())),
+                Instruction::C {
+                    dest: Dest::DM,
+                    comp: Comp::MPlusOne,
+                    jump: Jump::JNE
+                },
+                Instruction::A(AValue::Literal(7)),
+            ]
+        );
+    }
+
+    #[test]
+    fn swapped_operands() {
+        // Commutative operations can also be written with `A` or `M` first,
+        // like the VM translator does in `M=M+D`
+        let parsed = |source| -> Vec<Instruction> {
+            let statements = parse(Path::new("in.asm"), source).unwrap();
+            statements.into_iter().map(|s| s.instruction).collect()
+        };
+        let swapped = parsed("M=M+D\nD=A+D\nAM=M&D\nD=A&D\nM=M|D\nD=A|D\n");
+        assert_eq!(
+            swapped,
+            parsed("M=D+M\nD=D+A\nAM=D&M\nD=D&A\nM=D|M\nD=D|A\n")
+        );
+        // Printed in the usual order
+        assert_eq!(swapped[0].to_string(), "M=D+M");
+    }
+
+    #[test]
+    fn decode_inverts_encode() {
+        let mut symbols = SymbolTable::predefined();
+        for word in 0..=u16::MAX {
+            if let Some(instruction) = Instruction::decode(word) {
+                assert_eq!(instruction.encode(&mut symbols), Ok(Some(word)));
+                let reparsed = parse(Path::new("in.asm"), &instruction.to_string()).unwrap();
+                assert_eq!(reparsed[0].instruction, instruction);
+            }
+        }
+    }
+
+    #[test]
+    fn disassemble_round_trip() {
+        for hack_file in ["../add/Add.hack", "../max/Max.hack", "../pong/Pong.hack"] {
+            let hack_file = Path::new(env!("CARGO_MANIFEST_DIR")).join(hack_file);
+            let binary = fs::read_to_string(&hack_file).unwrap();
+            let asm = disassemble(&hack_file, &binary).unwrap();
+            assert_eq!(to_hack(&assemble_source(&hack_file, &asm).unwrap()), binary);
+        }
+    }
+
+    #[test]
+    fn disassemble_labels_and_annotations() {
+        let words = assemble_source(
+            Path::new("in.asm"),
+            "@R1\nD=M\n(TOP)\n@SCREEN\nM=D\n@1\nD=A\n@TOP\nD;JGT\n",
+        )
+        .unwrap();
+        assert_eq!(
+            disassemble(Path::new("in.hack"), &to_hack(&words)).unwrap(),
+            "  @1 // R1\n  D=M\n(L2)\n  @16384 // SCREEN\n  M=D\n  @1\n  D=A\n  @L2\n  D;JGT\n"
+        );
+        // Bits 13 and 14 clear, and an unused comp encoding
+        assert_eq!(
+            disassemble(Path::new("in.hack"), &to_hack(&[0x8000, 0xFFC0, 0xEC10])).unwrap(),
+            "  // .word 0x8000\n  // .word 0xffc0\n  D=A\n"
+        );
+    }
+
+    #[test]
+    fn user_defined_symbols() {
+        let mut symbols = SymbolTable::predefined();
+        symbols.define_from_str("UART=0x6001").unwrap();
+        let defs = "// extended board\nLEDS = 24578\n9LIVES=1\nTIMER=x\nSP=256\nUART=1\n";
+        let errors = symbols
+            .load_definitions(Path::new("board.sym"), defs)
+            .unwrap_err();
+        assert_eq!(
+            errors.iter().map(|e| e.kind.clone()).collect::<Vec<_>>(),
+            [
+                ErrorKind::BadDefinition("9LIVES=1".to_owned()),
+                ErrorKind::BadLiteral("x".to_owned()),
+                ErrorKind::RedefinedSymbol("SP".to_owned()),
+                ErrorKind::RedefinedSymbol("UART".to_owned()),
+            ]
+        );
+        let words = assemble_with(Path::new("in.asm"), "@UART\n@LEDS\n@LOOP\n", symbols);
+        assert_eq!(words.unwrap(), [24577, 24578, 16]);
+    }
+
+    #[test]
+    fn label_cannot_shadow_predefined_symbol() {
+        let errors = assemble_source(Path::new("in.asm"), "(KBD)\n@KBD\n0;JMP\n").unwrap_err();
+        assert_eq!(errors[0].kind, ErrorKind::RedefinedSymbol("KBD".to_owned()));
+    }
+
+    #[test]
+    fn listing_and_symbol_map() {
+        let source = "// count down\n(LOOP)\n  @i // counter\n  M=M-1\n\n  @LOOP\n  0;JMP\n";
+        let program =
+            assemble_program(Path::new("in.asm"), source, SymbolTable::predefined()).unwrap();
+        assert_eq!(
+            listing(&program, source),
+            [
+                "                               // count down",
+                "    0                          (LOOP)",
+                "    0  0000000000010000  0010    @i // counter",
+                "    1  1111110010001000  FC88    M=M-1",
+                "",
+                "    2  0000000000000000  0000    @LOOP",
+                "    3  1110101010000111  EA87    0;JMP",
+                "",
+            ]
+            .join("\n")
+        );
+        assert_eq!(
+            symbol_map(&program),
+            "    0  label     LOOP\n   16  variable  i\n"
+        );
+    }
+
+    #[test]
+    fn capacity_checks() {
+        let file = Path::new("big.asm");
+        let variables: String = (0..16369).map(|i| format!("@v{i}\n")).collect();
+        let errors = assemble_source(file, &variables).unwrap_err();
+        assert_eq!(
+            errors[0].to_string(),
+            "big.asm:16369:1: error: variable `v16368` doesn't fit below the screen memory map at 16384"
+        );
+        assert_eq!(errors.len(), 1);
+        assert!(assemble_source(file, &variables[..variables.rfind("@v").unwrap()]).is_ok());
+
+        let errors = assemble_source(file, &"D=0\n".repeat(ROM_SIZE + 2)).unwrap_err();
+        assert_eq!(
+            errors[0].to_string(),
+            "big.asm:32769:1: error: program has 32770 words, the ROM only fits 32768"
+        );
+        assert!(assemble_source(file, &"D=0\n".repeat(ROM_SIZE)).is_ok());
+
+        // Addresses past the 16 bits of a word
+        let source = "D=0\n".repeat(70_000) + "(END)\n@END\n0;JMP\n";
+        let errors = assemble_source(file, &source).unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].kind, ErrorKind::RomOverflow(70_002));
+        let variables: String = (0..65_521).map(|i| format!("@v{i}\n")).collect();
+        let errors = assemble_source(file, &variables).unwrap_err();
+        let overflows: Vec<_> = errors
+            .into_iter()
+            .filter(|e| matches!(e.kind, ErrorKind::RamOverflow(_)))
+            .map(|e| e.location.unwrap().line)
+            .collect();
+        assert_eq!(overflows, [16369, 65521]);
+    }
+
+    #[test]
+    fn size_report_by_label() {
+        let source = "@5\nD=A\n(Main.main)\n@Main.main$L\n(Main.main$L)\nD;JGT\n\
+            @x\nM=D\n(Sys.init)\n@Main.main\n0;JMP\n";
+        let program =
+            assemble_program(Path::new("in.asm"), source, SymbolTable::predefined()).unwrap();
+        assert_eq!(
+            size_report(&program),
+            [
+                " words    share  address  label",
+                "     4    0.01%        2  Main.main",
+                "     2    0.01%        0  (start)",
+                "     2    0.01%        6  Sys.init",
+                "     8    0.02%           ROM total, 32760 words free",
+                "     1    0.01%           RAM for variables, 16367 words free",
+                "",
+            ]
+            .join("\n")
+        );
+    }
+
+    #[test]
+    fn source_map_formats() {
+        let source =
+            "// push constant 7\n@7\nD=A\n(Main.f)\n// add\n@SP\n(Main.f$ret.0)\nM=M+D // \"sum\"\n";
+        let program =
+            assemble_program(Path::new("in.asm"), source, SymbolTable::predefined()).unwrap();
+        assert_eq!(
+            source_map(&program, SourceMapFormat::Tsv),
+            [
+                "address\tfile\tline\tlabel\tcomment",
+                "0\tin.asm\t2\t\tpush constant 7",
+                "1\tin.asm\t3\t\tpush constant 7",
+                "2\tin.asm\t6\tMain.f\tadd",
+                // Local labels are part of the enclosing function
+                "3\tin.asm\t8\tMain.f\tadd",
+                "",
+            ]
+            .join("\n")
+        );
+        let json = source_map(&program, SourceMapFormat::Json);
+        assert!(json.starts_with(
+            "[\n  {\"address\": 0, \"file\": \"in.asm\", \"line\": 2, \"label\": null, \
+            \"comment\": \"push constant 7\"},\n"
+        ));
+        assert!(json.ends_with(
+            "{\"address\": 3, \"file\": \"in.asm\", \"line\": 8, \"label\": \"Main.f\", \
+            \"comment\": \"add\"}\n]\n"
+        ));
+    }
+
+    #[test]
+    fn output_formats() {
+        let words = [0x0010, 0xFC88, 0, 0, 0];
+        let render = |format| OutputFormat::render(format, &words).unwrap();
+        assert_eq!(render(OutputFormat::BinLe)[..4], [0x10, 0x00, 0x88, 0xFC]);
+        assert_eq!(render(OutputFormat::BinBe)[..4], [0x00, 0x10, 0xFC, 0x88]);
+        assert_eq!(
+            String::from_utf8(render(OutputFormat::IntelHex)).unwrap(),
+            ":0A0000000010FC8800000000000062\n:00000001FF\n"
+        );
+        assert_eq!(
+            String::from_utf8(render(OutputFormat::ReadMemH)).unwrap(),
+            "0010\nfc88\n0000\n0000\n0000\n"
+        );
+        assert_eq!(
+            String::from_utf8(render(OutputFormat::Logisim)).unwrap(),
+            "v2.0 raw\n10 fc88 3*0\n"
+        );
+        assert_eq!(
+            OutputFormat::Hack.render(&[0; ROM_SIZE + 1]),
+            Err(ErrorKind::RomOverflow(ROM_SIZE + 1))
+        );
+    }
+
+    #[test]
+    fn a_instruction_operands() {
+        let source =
+            "@0x4000\n@0b1010\n@'A'\n@SCREEN+32\n(table)\n@table+3\n@KBD-SCREEN - 1\n@'+'+1\n";
+        let words = assemble_source(Path::new("in.asm"), source).unwrap();
+        assert_eq!(words, [0x4000, 10, 65, 16416, 7, 8191, 44]);
+    }
+
+    #[test]
+    fn a_instruction_range() {
+        let source = "@40000\n@0x8000\n@SCREEN+0x4000\n@R0-1\n@0x\n@'ab'\n@1+\n";
+        let errors = assemble_source(Path::new("in.asm"), source).unwrap_err();
+        assert_eq!(
+            errors.iter().map(|e| e.kind.clone()).collect::<Vec<_>>(),
+            [
+                ErrorKind::OutOfRange(40000),
+                ErrorKind::OutOfRange(0x8000),
+                ErrorKind::BadLiteral("0x".to_owned()),
+                ErrorKind::BadLiteral("'ab'".to_owned()),
+                ErrorKind::BadLiteral("1+".to_owned()),
+            ]
+        );
+        let errors = assemble_source(Path::new("in.asm"), "@SCREEN+0x4000\n@R0-1\n").unwrap_err();
+        assert_eq!(
+            errors.iter().map(|e| e.kind.clone()).collect::<Vec<_>>(),
+            [ErrorKind::OutOfRange(0x8000), ErrorKind::OutOfRange(-1)]
+        );
+        assert_eq!(
+            errors[1].to_string(),
+            "in.asm:2:1: error: value -1 doesn't fit into the 15 bits of an A-instruction"
+        );
+    }
+
+    #[test]
+    fn macro_errors_point_at_source() {
+        let source = [
+            ".macro INC addr",
+            "  @\\addr",
+            "  M=M+X",
+            ".endm",
+            "INC R0",
+            "INC",
+            ".endm",
+            ".macro FOREVER",
+            "  FOREVER",
+            ".endm",
+            "FOREVER",
+            ".macro OPEN",
+        ]
+        .join("\n");
+        let errors = parse(Path::new("in.asm"), &source).unwrap_err();
+        let found: Vec<_> = errors
+            .iter()
+            .map(|e| (e.location.clone().unwrap().line, e.to_string()))
+            .collect();
+        assert_eq!(
+            found,
+            [
+                (
+                    3,
+                    "in.asm:3:5: error: unknown comp mnemonic `M+X`".to_owned()
+                ),
+                (
+                    6,
+                    "in.asm:6:1: error: `INC` takes 1 argument(s), got 0".to_owned()
+                ),
+                (7, "in.asm:7:1: error: bad directive `.endm`".to_owned()),
+                (
+                    9,
+                    "in.asm:9:3: error: expansion of `FOREVER` nested too deep".to_owned()
+                ),
+                (
+                    12,
+                    "in.asm:12:1: error: `OPEN` is missing `.endm`".to_owned()
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn include_cycle() {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("../macros");
+        let errors = parse(&dir.join("Self.asm"), "@1\n.include \"Self.asm\"\n").unwrap_err();
+        assert_eq!(
+            errors[0].kind,
+            ErrorKind::IncludeCycle("Self.asm".to_owned())
+        );
+        let statements = parse(&dir.join("Main.asm"), ".include \"Stack.asm\"\nPOP_D\n").unwrap();
+        assert_eq!(statements.len(), 3);
+        assert!(statements[0].file.ends_with("Stack.asm"));
+        assert_eq!(
+            (statements[0].location.line, statements[0].top_level_line),
+            (14, 2)
+        );
+    }
+
+    #[test]
+    fn lint_warnings() {
+        let source = [
+            "(LOOP)",    // 1
+            "  @count",  // 2
+            "  D;JGT",   // 3 jumps to a variable
+            "  @SP",     // 4
+            "  AM=M-1",  // 5 stack pop, writes M back where it read
+            "  @LOOP",   // 6
+            "  M=D",     // 7 writes to a ROM address
+            "  @0x7000", // 8
+            "  M=D",     // 9 writes above the RAM
+            "  @Loop",   // 10 typo of LOOP
+            "  0;JMP",   // 11 jumps to a variable
+            "  D=0",     // 12 unreachable
+            "  @LOOP",   // 13
+            "  0;JMP",   // 14
+            "(END)",     // 15
+            "  @count",  // 16
+            "  @END",    // 17
+            "  0;JMP",   // 18
+            "(PTR)",     // 19
+            "  A=M",     // 20 pointer load
+            "  A=M;JNE", // 21 jumps to the previous A
+        ]
+        .join("\n");
+        let program =
+            assemble_program(Path::new("in.asm"), &source, SymbolTable::predefined()).unwrap();
+        let found: Vec<_> = lint(&program)
+            .into_iter()
+            .map(|w| (w.location.line, w.kind))
+            .collect();
+        assert_eq!(
+            found,
+            [
+                (3, WarningKind::JumpToData("count".to_owned())),
+                (7, WarningKind::WriteToRomAddress("LOOP".to_owned())),
+                (9, WarningKind::WriteToRomAddress("28672".to_owned())),
+                (11, WarningKind::JumpToData("Loop".to_owned())),
+                (12, WarningKind::Unreachable),
+                (21, WarningKind::MemoryIntoA("A=M;JNE".to_owned())),
+                (
+                    10,
+                    WarningKind::LabelTypo {
+                        variable: "Loop".to_owned(),
+                        label: "LOOP".to_owned()
+                    }
+                ),
+            ]
+        );
+    }
+
+    /// Takes the path to an `*.asm*` file relative to the MANIFEST_DIR and
+    /// tests the generated assembly against a `*.hack` file next to it.
+    fn test_assemble(asm_file: &str) {
+        let asm_file = {
+            let mut p = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+            p.push(asm_file);
+            p
+        };
+        assert_eq!(asm_file.extension().unwrap(), "asm");
+        let expected = fs::read_to_string(asm_file.with_extension("hack"))
+            .expect("Could not read reference file.");
+
+        let result = assemble(asm_file.as_path()).unwrap();
+        assert_eq!(to_hack(&result), expected);
+    }
+}
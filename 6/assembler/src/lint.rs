@@ -0,0 +1,207 @@
+use crate::{
+    error::Location,
+    instruction::{AValue, Instruction, Jump},
+    parser::Statement,
+    symbols::{SymbolKind, SymbolTable},
+    Program,
+};
+
+use std::{
+    collections::HashMap,
+    fmt::{self, Display},
+    path::PathBuf,
+};
+
+/// Keyboard register, the first address above the writable RAM
+const KBD: i64 = 24576;
+
+/// A likely bug that still assembles.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Warning {
+    pub file: PathBuf,
+    pub location: Location,
+    pub kind: WarningKind,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WarningKind {
+    /// Jump whose target was loaded from something else than a label
+    JumpToData(String),
+    /// C-instruction that computes `A` from `M` and jumps, which goes to the
+    /// old `A` rather than the loaded address. Pointer loads like `A=M` and
+    /// the stack pop `AM=M-1` don't jump and are not flagged.
+    MemoryIntoA(String),
+    /// Variable used only once, with a label of almost the same name
+    LabelTypo { variable: String, label: String },
+    /// Instruction after an unconditional jump without a label in between
+    Unreachable,
+    /// Write to `M` where `A` holds a ROM address or an address above the RAM
+    WriteToRomAddress(String),
+}
+
+/// Checks an assembled program for common Hack pitfalls.
+pub fn lint(program: &Program) -> Vec<Warning> {
+    let mut warnings = Vec::new();
+    let warn = |s: &Statement, kind| Warning {
+        file: s.file.clone(),
+        location: s.location.clone(),
+        kind,
+    };
+    let symbols = &program.symbols;
+    // Previous instruction, `None` after a label as it can be reached from elsewhere
+    let mut previous: Option<&Statement> = None;
+    let mut reachable = true;
+    for s in &program.statements {
+        let loaded = match previous.map(|p| &p.instruction) {
+            Some(Instruction::A(value)) => Some(value),
+            _ => None,
+        };
+        if let Instruction::Label(_) = s.instruction {
+            reachable = true;
+            previous = None;
+            continue;
+        }
+        if !reachable {
+            warnings.push(warn(s, WarningKind::Unreachable));
+            // Only report the first instruction of unreachable code
+            reachable = true;
+        }
+        match &s.instruction {
+            Instruction::A(_) | Instruction::Label(_) => {}
+            Instruction::C { dest, comp, jump } => {
+                if comp.reads_m() && dest.writes_a() && *jump != Jump::Null {
+                    let text = s.instruction.to_string();
+                    warnings.push(warn(s, WarningKind::MemoryIntoA(text)));
+                }
+                if *jump != Jump::Null {
+                    if let Some(value) = loaded.filter(|v| !refers_to_label(v, symbols)) {
+                        warnings.push(warn(s, WarningKind::JumpToData(value.to_string())));
+                    }
+                }
+                if dest.writes_m() {
+                    if let Some(value) = loaded.filter(|v| {
+                        refers_to_label(v, symbols) || v.value(symbols).is_some_and(|a| a >= KBD)
+                    }) {
+                        warnings.push(warn(s, WarningKind::WriteToRomAddress(value.to_string())));
+                    }
+                }
+                if *jump == Jump::JMP {
+                    reachable = false;
+                }
+            }
+        }
+        previous = Some(s);
+    }
+    warnings.extend(
+        label_typos(program)
+            .into_iter()
+            .map(|(s, kind)| warn(s, kind)),
+    );
+    warnings
+}
+
+/// Variables that are used only once while a label has nearly the same name
+fn label_typos(program: &Program) -> Vec<(&Statement, WarningKind)> {
+    let mut uses: HashMap<&str, Vec<&Statement>> = HashMap::new();
+    for s in &program.statements {
+        if let Instruction::A(value) = &s.instruction {
+            for name in symbol_names(value) {
+                uses.entry(name).or_default().push(s);
+            }
+        }
+    }
+    let labels: Vec<&str> = program
+        .symbols
+        .iter()
+        .filter(|(_, s)| s.kind == SymbolKind::Label)
+        .map(|(name, _)| name)
+        .collect();
+    let mut typos: Vec<_> = program
+        .symbols
+        .iter()
+        .filter(|(_, s)| s.kind == SymbolKind::Variable)
+        .filter_map(|(variable, _)| {
+            let [s] = uses.get(variable)?.as_slice() else {
+                return None;
+            };
+            let label = labels.iter().find(|l| nearly_equal(variable, l))?;
+            let kind = WarningKind::LabelTypo {
+                variable: variable.to_owned(),
+                label: label.to_string(),
+            };
+            Some((*s, kind))
+        })
+        .collect();
+    typos.sort_by_key(|(s, _)| s.location.line);
+    typos
+}
+
+fn symbol_names(value: &AValue) -> Vec<&str> {
+    match value {
+        AValue::Literal(_) => Vec::new(),
+        AValue::Symbol(name) => vec![name],
+        AValue::Sum(lhs, _, rhs) => [symbol_names(lhs), symbol_names(rhs)].concat(),
+    }
+}
+
+fn refers_to_label(value: &AValue, symbols: &SymbolTable) -> bool {
+    symbol_names(value)
+        .into_iter()
+        .any(|name| symbols.kind(name) == Some(SymbolKind::Label))
+}
+
+/// Equal when ignoring case or with an edit distance of one
+fn nearly_equal(a: &str, b: &str) -> bool {
+    if a.eq_ignore_ascii_case(b) {
+        return true;
+    }
+    let (a, b): (Vec<char>, Vec<char>) = (a.chars().collect(), b.chars().collect());
+    let (short, long) = if a.len() <= b.len() { (a, b) } else { (b, a) };
+    if long.len() - short.len() > 1 {
+        return false;
+    }
+    let prefix = short.iter().zip(&long).take_while(|(x, y)| x == y).count();
+    if short.len() == long.len() {
+        // Substitution
+        short[prefix + 1..] == long[prefix + 1..]
+    } else {
+        // Insertion
+        short[prefix..] == long[prefix + 1..]
+    }
+}
+
+impl Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let Location { line, columns } = &self.location;
+        write!(
+            f,
+            "{}:{line}:{}: warning: {}",
+            self.file.display(),
+            columns.start,
+            self.kind
+        )
+    }
+}
+
+impl Display for WarningKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WarningKind::JumpToData(value) => {
+                write!(f, "jump target `{value}` is not a label")
+            }
+            WarningKind::MemoryIntoA(text) => {
+                write!(f, "`{text}` loads A from M but jumps to the previous A")
+            }
+            WarningKind::LabelTypo { variable, label } => write!(
+                f,
+                "`{variable}` is used only once and becomes a variable, did you mean label `{label}`?"
+            ),
+            WarningKind::Unreachable => {
+                write!(f, "unreachable code after unconditional jump")
+            }
+            WarningKind::WriteToRomAddress(value) => {
+                write!(f, "writes to `{value}`, which is no RAM address")
+            }
+        }
+    }
+}
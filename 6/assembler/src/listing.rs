@@ -0,0 +1,104 @@
+use crate::{
+    instruction::Instruction,
+    output::ROM_SIZE,
+    symbols::{Symbol, SymbolKind, SCREEN},
+    Program,
+};
+
+use std::{cmp::Reverse, collections::HashMap};
+
+/// Every line of `source` prefixed with the ROM address and the binary and
+/// hex encoding of the instruction on it. Words generated by a macro
+/// invocation or `.include` follow on extra lines.
+pub fn listing(program: &Program, source: &str) -> String {
+    let mut by_line: HashMap<_, Vec<_>> = HashMap::new();
+    for (address, word, s) in program.rom() {
+        by_line
+            .entry(s.top_level_line)
+            .or_default()
+            .push((address, word));
+    }
+    let mut result = String::new();
+    for (i, line) in source.lines().enumerate() {
+        let mut rom = by_line.remove(&(i + 1)).unwrap_or_default();
+        if rom.len() > 1 {
+            rom.retain(|(_, word)| word.is_some());
+        }
+        let mut rows = rom.iter().map(|(address, word)| match word {
+            Some(word) => format!("{address:5}  {word:016b}  {word:04X}"),
+            None => format!("{address:5}"),
+        });
+        let listed = format!("{:29}  {line}", rows.next().unwrap_or_default());
+        result += listed.trim_end();
+        result.push('\n');
+        for row in rows {
+            result += &row;
+            result.push('\n');
+        }
+    }
+    result
+}
+
+/// Address of every label and variable, labels first.
+pub fn symbol_map(program: &Program) -> String {
+    let mut symbols: Vec<(&str, Symbol)> = program
+        .symbols
+        .iter()
+        .filter(|(_, s)| s.kind != SymbolKind::Predefined)
+        .collect();
+    symbols.sort_by_key(|(name, s)| (s.kind, s.address, *name));
+    symbols
+        .iter()
+        .map(|(name, s)| {
+            let kind = match s.kind {
+                SymbolKind::Label => "label",
+                _ => "variable",
+            };
+            format!("{:5}  {kind:8}  {name}\n", s.address)
+        })
+        .collect()
+}
+
+/// Number of words between each label and the next one, largest first,
+/// followed by the total ROM and RAM usage.
+///
+/// Labels containing `$` are local to a VM function and counted towards the
+/// label before them. Code before the first label is listed as `(start)`.
+pub fn size_report(program: &Program) -> String {
+    let mut sections = vec![(0, "(start)", 0)];
+    for (address, word, s) in program.rom() {
+        match &s.instruction {
+            Instruction::Label(name) if !name.contains('$') => sections.push((address, name, 0)),
+            _ if word.is_some() => sections.last_mut().expect("Never empty").2 += 1,
+            _ => {}
+        }
+    }
+    sections.retain(|(_, _, words)| *words > 0);
+    sections.sort_by_key(|&(address, _, words)| (Reverse(words), address));
+
+    let share = |words: usize, capacity: usize| 100.0 * words as f64 / capacity as f64;
+    let mut result = String::from(" words    share  address  label\n");
+    for (address, name, words) in sections {
+        let share = share(words, ROM_SIZE);
+        result += &format!("{words:6}  {share:6.2}%  {address:7}  {name}\n");
+    }
+    let used = program.words.len();
+    result += &format!(
+        "{used:6}  {:6.2}%           ROM total, {} words free\n",
+        share(used, ROM_SIZE),
+        ROM_SIZE.saturating_sub(used)
+    );
+    let variables = program
+        .symbols
+        .iter()
+        .filter(|(_, s)| s.kind == SymbolKind::Variable)
+        .count();
+    // Variables are allocated from address 16 up to the screen memory map
+    let capacity = usize::from(SCREEN) - 16;
+    result += &format!(
+        "{variables:6}  {:6.2}%           RAM for variables, {} words free\n",
+        share(variables, capacity),
+        capacity.saturating_sub(variables)
+    );
+    result
+}
@@ -0,0 +1,109 @@
+use crate::{
+    error::ErrorKind,
+    instruction::{AValue, Instruction},
+    parser::{Line, Statement},
+};
+
+use std::{
+    cmp::Reverse,
+    collections::HashSet,
+    path::{Path, PathBuf},
+};
+
+/// A `.macro NAME params... .endm` definition.
+///
+/// Parameters are referenced as `\param` in the body. Labels defined in the
+/// body are local, they get a unique suffix on every expansion.
+pub(crate) struct Macro {
+    pub name: String,
+    /// File the macro is defined in
+    pub file: PathBuf,
+    pub params: Vec<String>,
+    pub body: Vec<BodyLine>,
+}
+
+pub(crate) struct BodyLine {
+    pub number: usize,
+    pub column: usize,
+    pub text: String,
+}
+
+impl Macro {
+    /// Reads the definition up to `.endm` from `lines`, `header` is the text
+    /// after `.macro` on line `l`.
+    pub fn define<'a>(
+        file: &Path,
+        l: &Line,
+        header: &str,
+        lines: &mut impl Iterator<Item = Line<'a>>,
+    ) -> Result<Self, ErrorKind> {
+        let mut words = split_arguments(header);
+        let name = words
+            .next()
+            .ok_or_else(|| ErrorKind::BadDirective(l.text.to_owned()))?;
+        let params = words.map(str::to_owned).collect();
+        let mut body = Vec::new();
+        for line in lines {
+            if line.text == ".endm" {
+                return Ok(Macro {
+                    name: name.to_owned(),
+                    file: file.to_owned(),
+                    params,
+                    body,
+                });
+            }
+            body.push(BodyLine {
+                number: line.number,
+                column: line.column,
+                text: line.text.to_owned(),
+            });
+        }
+        Err(ErrorKind::BadMacro(format!("`{name}` is missing `.endm`")))
+    }
+
+    /// Replaces every `\param` in `text` with the matching argument
+    pub fn substitute(&self, text: &str, args: &[&str]) -> String {
+        // Longest first, so that `\a` doesn't replace the start of `\ab`
+        let mut params: Vec<_> = self.params.iter().zip(args).collect();
+        params.sort_by_key(|(p, _)| Reverse(p.len()));
+        params.into_iter().fold(text.to_owned(), |text, (p, arg)| {
+            text.replace(&format!("\\{p}"), arg)
+        })
+    }
+}
+
+/// Macro parameters and arguments may be separated by commas or whitespace
+pub(crate) fn split_arguments(s: &str) -> impl Iterator<Item = &str> {
+    s.split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|a| !a.is_empty())
+}
+
+/// Appends `suffix` to every label defined in `statements` and to the
+/// references to them.
+pub(crate) fn rename_local_labels(statements: &mut [Statement], suffix: &str) {
+    let local: HashSet<String> = statements
+        .iter()
+        .filter_map(|s| match &s.instruction {
+            Instruction::Label(name) => Some(name.clone()),
+            _ => None,
+        })
+        .collect();
+    for s in statements {
+        match &mut s.instruction {
+            Instruction::Label(name) => name.push_str(suffix),
+            Instruction::A(value) => rename(value, &local, suffix),
+            Instruction::C { .. } => {}
+        }
+    }
+}
+
+fn rename(value: &mut AValue, local: &HashSet<String>, suffix: &str) {
+    match value {
+        AValue::Symbol(name) if local.contains(name) => name.push_str(suffix),
+        AValue::Sum(lhs, _, rhs) => {
+            rename(lhs, local, suffix);
+            rename(rhs, local, suffix);
+        }
+        _ => {}
+    }
+}
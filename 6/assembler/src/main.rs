@@ -1,225 +1,136 @@
+use assembler::{
+    assemble_program, disassemble, lint, listing, read_source, size_report, source_map, symbol_map,
+    AsmError, ErrorKind, OutputFormat, SourceMapFormat, SymbolTable,
+};
+
 use std::{
-    collections::{hash_map::Entry, HashMap},
     env, fs,
+    io::{self, Write},
     path::Path,
+    process,
 };
 
-fn main() {
-    let mut args = env::args();
-    if args.len() != 2 {
-        panic!("Expect single parameter to `*.asm` file.");
-    }
-    let filename = args.next_back().unwrap();
-    let result = assemble(Path::new(&filename));
+const USAGE: &str = "Usage: assembler [--disassemble] [--define NAME=VALUE]... \
+[--symbols FILE]... [--listing] [--symbol-map] [--format FORMAT] [--lint] [--size-report] [--source-map json|tsv] <file>";
 
-    // I prefer to print to stdout, users can easily pipe to a file
-    print!("{result}");
+/// Prints `message` with the usage and exits with status 2
+fn usage_error(message: &str) -> ! {
+    eprintln!("error: {message}\n{USAGE}");
+    process::exit(2)
 }
 
-#[cfg(test)]
-mod test {
-    use super::*;
-    use std::path::PathBuf;
-
-    #[test]
-    fn add() {
-        test_assemble("../add/Add.asm");
-    }
-
-    #[test]
-    fn max_l() {
-        test_assemble("../max/MaxL.asm");
-    }
-
-    #[test]
-    fn pong_l() {
-        test_assemble("../pong/PongL.asm");
-    }
-
-    #[test]
-    fn max() {
-        test_assemble("../max/Max.asm");
-    }
-
-    #[test]
-    fn pong() {
-        test_assemble("../pong/Pong.asm");
-    }
-
-    /// Takes the path to an `*.asm*` file relative to the MANIFEST_DIR and
-    /// tests the generated assembly against a `*.hack` file next to it.
-    fn test_assemble(asm_file: &str) {
-        let asm_file = {
-            let mut p = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
-            p.push(asm_file);
-            p
+fn main() {
+    let mut args = env::args().skip(1);
+    let mut disassemble_mode = false;
+    let mut write_listing = false;
+    let mut write_symbol_map = false;
+    let mut format = OutputFormat::Hack;
+    let mut run_lint = false;
+    let mut print_size_report = false;
+    let mut source_map_format: Option<SourceMapFormat> = None;
+    let mut symbols = SymbolTable::predefined();
+    let mut errors = Vec::new();
+    let mut filename = None;
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .unwrap_or_else(|| usage_error(&format!("missing value for `{arg}`")))
         };
-        assert_eq!(asm_file.extension().unwrap(), "asm");
-        let expected = fs::read_to_string(asm_file.with_extension("hack"))
-            .expect("Could not read reference file.");
-
-        let result = assemble(asm_file.as_path());
-        assert_eq!(result, expected);
+        match arg.as_str() {
+            "--disassemble" => disassemble_mode = true,
+            "--listing" => write_listing = true,
+            "--symbol-map" => write_symbol_map = true,
+            "--lint" => run_lint = true,
+            "--size-report" => print_size_report = true,
+            "--format" => {
+                format = value().parse().unwrap_or_else(|e: String| usage_error(&e));
+            }
+            "--source-map" => {
+                let format = value().parse();
+                source_map_format = Some(format.unwrap_or_else(|e: String| usage_error(&e)));
+            }
+            "--define" => {
+                let definition = value();
+                if let Err(kind) = symbols.define_from_str(&definition) {
+                    errors.push(AsmError {
+                        file: "--define".into(),
+                        location: None,
+                        kind,
+                    });
+                }
+            }
+            "--symbols" => {
+                let file = value();
+                let loaded = read_source(Path::new(&file))
+                    .and_then(|source| symbols.load_definitions(Path::new(&file), &source));
+                errors.extend(loaded.err().into_iter().flatten());
+            }
+            _ if arg.starts_with("--") => usage_error(&format!("unknown option `{arg}`")),
+            _ if filename.is_none() => filename = Some(arg),
+            _ => usage_error(&format!("unexpected argument `{arg}`")),
+        }
     }
-}
-
-fn assemble(asm_file: &Path) -> String {
-    let asm_file = fs::read_to_string(asm_file)
-        .unwrap_or_else(|_| panic!("Couldn't read {}.", asm_file.display()));
-
-    let mut symbols = first_pass(&asm_file);
-    let mut last_symbol_address = 15;
-    let mut result = trimmed_lines(&asm_file)
-        .filter(|l| !l.starts_with('('))
-        .map(|l| {
-            if let Some(a_expr) = l.strip_prefix('@') {
-                let value = if is_symbol(a_expr) {
-                    *symbols.entry(a_expr).or_insert_with(|| {
-                        last_symbol_address += 1;
-                        last_symbol_address
-                    })
-                } else {
-                    a_expr
-                        .parse::<i32>()
-                        .unwrap_or_else(|_| panic!("Couldn't parse A-instruction `{l}`"))
-                };
-                format!("0{value:015b}")
-            } else {
-                // C-instruction
-
-                let (dest, l) = if let Some((dest, rest)) = l.split_once('=') {
-                    let dest = match dest {
-                        "M" => "001",
-                        "D" => "010",
-                        "DM" | "MD" => "011", // MD is used in PongL.asm
-                        "A" => "100",
-                        "AM" => "101",
-                        "AD" => "110",
-                        "ADM" => "111",
-                        _ => panic! {"Invalid dest: `{dest}`"},
-                    };
-                    (dest, rest)
-                } else {
-                    ("000", l)
-                };
-                let (comp, jump) = if let Some((comp, jump)) = l.split_once(';') {
-                    let jump = match jump {
-                        "JGT" => "001",
-                        "JEQ" => "010",
-                        "JGE" => "011",
-                        "JLT" => "100",
-                        "JNE" => "101",
-                        "JLE" => "110",
-                        "JMP" => "111",
-                        _ => panic! {"Invalid jump: `{jump}`"},
-                    };
-                    (comp, jump)
-                } else {
-                    (l, "000")
-                };
-                let comp = match comp {
-                    "0" => "0101010",
-                    "1" => "0111111",
-                    "-1" => "0111010",
-                    "D" => "0001100",
-                    "A" => "0110000",
-                    "!D" => "0001101",
-                    "!A" => "0110001",
-                    "-D" => "0001111",
-                    "-A" => "0110011",
-                    "D+1" => "0011111",
-                    "A+1" => "0110111",
-                    "D-1" => "0001110",
-                    "A-1" => "0110010",
-                    "D+A" => "0000010",
-                    "D-A" => "0010011",
-                    "A-D" => "0000111",
-                    "D&A" => "0000000",
-                    "D|A" => "0010101",
-                    "M" => "1110000",
-                    "!M" => "1110001",
-                    "-M" => "1110011",
-                    "M+1" => "1110111",
-                    "M-1" => "1110010",
-                    "D+M" => "1000010",
-                    "D-M" => "1010011",
-                    "M-D" => "1000111",
-                    "D&M" => "1000000",
-                    "D|M" => "1010101",
-                    _ => panic! {"Invalid comp: `{comp}`"},
-                };
-
-                format! {"111{comp}{dest}{jump}"}
+    let Some(filename) = filename else {
+        usage_error("missing file");
+    };
+    let path = Path::new(&filename);
+
+    let result = if !errors.is_empty() {
+        Err(errors)
+    } else if disassemble_mode {
+        read_source(path).and_then(|source| disassemble(path, &source).map(String::into_bytes))
+    } else {
+        read_source(path).and_then(|source| {
+            let program = assemble_program(path, &source, symbols)?;
+            if run_lint {
+                for warning in lint(&program) {
+                    eprintln!("{warning}");
+                }
+            }
+            if print_size_report {
+                eprint!("{}", size_report(&program));
+            }
+            // Listing and symbol map are placed next to the `*.asm` file
+            if write_listing {
+                write_file(&path.with_extension("lst"), &listing(&program, &source))?;
             }
+            if write_symbol_map {
+                write_file(&path.with_extension("sym"), &symbol_map(&program))?;
+            }
+            if let Some(format) = source_map_format {
+                let map = source_map(&program, format);
+                write_file(&path.with_extension(format.extension()), &map)?;
+            }
+            format.render(&program.words).map_err(|kind| {
+                vec![AsmError {
+                    file: path.to_owned(),
+                    location: None,
+                    kind,
+                }]
+            })
         })
-        .collect::<Vec<String>>()
-        .join("\n");
-    result.push('\n');
-    result
-}
-
-#[must_use]
-fn first_pass(asm_file: &str) -> HashMap<&str, i32> {
-    let mut symbols = HashMap::from([
-        ("R0", 0),
-        ("R1", 1),
-        ("R2", 2),
-        ("R3", 3),
-        ("R4", 4),
-        ("R5", 5),
-        ("R6", 6),
-        ("R7", 7),
-        ("R8", 8),
-        ("R9", 9),
-        ("R10", 10),
-        ("R11", 11),
-        ("R12", 12),
-        ("R13", 13),
-        ("R14", 14),
-        ("R15", 15),
-        ("SP", 0),
-        ("LCL", 1),
-        ("ARG", 2),
-        ("THIS", 3),
-        ("THAT", 4),
-        ("SCREEN", 16384),
-        ("KBD", 24576),
-        ("LOOP", 4),
-        ("STOP", 18),
-        ("i", 16),
-        ("sum", 17),
-    ]);
-    let mut byte_offset = 0;
-    trimmed_lines(asm_file).for_each(|l| {
-        if let Some(sym_name) = l.strip_prefix('(') {
-            let sym_name = sym_name
-                .strip_suffix(')')
-                .expect("Missing closing bracket for symbol.");
-            symbols.entry(sym_name).or_insert(byte_offset);
-        } else {
-            byte_offset += 1;
+    };
+    match result {
+        // I prefer to print to stdout, users can easily pipe to a file
+        Ok(result) => io::stdout()
+            .write_all(&result)
+            .expect("Failed to write output"),
+        Err(errors) => {
+            for e in &errors {
+                eprintln!("{e}");
+            }
+            eprintln!("{} error(s), no output generated", errors.len());
+            process::exit(1);
         }
-    });
-    symbols
-}
-
-fn trimmed_lines(s: &str) -> impl Iterator<Item = &str> {
-    s.lines()
-        .map(|l| strip_comment(l).trim())
-        .filter(|l| !l.is_empty())
-}
-
-fn strip_comment(s: &str) -> &str {
-    if let Some((content, _comment)) = s.split_once("//") {
-        content
-    } else {
-        s
     }
 }
 
-fn is_symbol(s: &str) -> bool {
-    !s.chars()
-        .next()
-        .expect("Identifier expected")
-        .is_ascii_digit()
+fn write_file(path: &Path, content: &str) -> Result<(), Vec<AsmError>> {
+    fs::write(path, content).map_err(|e| {
+        vec![AsmError {
+            file: path.to_owned(),
+            location: None,
+            kind: ErrorKind::Io(format!("couldn't write file: {e}")),
+        }]
+    })
 }
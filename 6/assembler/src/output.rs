@@ -0,0 +1,116 @@
+use crate::{error::ErrorKind, to_hack};
+
+use std::{fmt::Write, str::FromStr};
+
+/// Number of words in the Hack instruction memory
+pub const ROM_SIZE: usize = 32768;
+
+/// File formats the machine words can be written in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputFormat {
+    /// Textual `*.hack`, one `0`/`1` string per word
+    Hack,
+    /// Raw 16-bit words, little-endian
+    BinLe,
+    /// Raw 16-bit words, big-endian
+    BinBe,
+    /// Intel HEX with byte addresses, each word stored big-endian
+    IntelHex,
+    /// Verilog `$readmemh`, one hex word per line
+    ReadMemH,
+    /// Verilog `$readmemb`, one binary word per line
+    ReadMemB,
+    /// Logisim "v2.0 raw" ROM image
+    Logisim,
+}
+
+impl OutputFormat {
+    pub fn render(self, words: &[u16]) -> Result<Vec<u8>, ErrorKind> {
+        if words.len() > ROM_SIZE {
+            return Err(ErrorKind::RomOverflow(words.len()));
+        }
+        Ok(match self {
+            OutputFormat::Hack => to_hack(words).into_bytes(),
+            OutputFormat::BinLe => words.iter().flat_map(|w| w.to_le_bytes()).collect(),
+            OutputFormat::BinBe => words.iter().flat_map(|w| w.to_be_bytes()).collect(),
+            OutputFormat::IntelHex => intel_hex(words).into_bytes(),
+            OutputFormat::ReadMemH => words
+                .iter()
+                .map(|w| format!("{w:04x}\n"))
+                .collect::<String>()
+                .into_bytes(),
+            OutputFormat::ReadMemB => to_hack(words).into_bytes(),
+            OutputFormat::Logisim => logisim(words).into_bytes(),
+        })
+    }
+}
+
+fn intel_hex(words: &[u16]) -> String {
+    let mut result = String::new();
+    for (i, chunk) in words.chunks(8).enumerate() {
+        let address = (i * 16) as u16;
+        let data: Vec<u8> = chunk.iter().flat_map(|w| w.to_be_bytes()).collect();
+        let mut record = vec![data.len() as u8];
+        record.extend(address.to_be_bytes());
+        record.push(0x00); // Data record
+        record.extend(data);
+        write_record(&mut result, &record);
+    }
+    write_record(&mut result, &[0x00, 0x00, 0x00, 0x01]); // End of file record
+    result
+}
+
+fn write_record(out: &mut String, record: &[u8]) {
+    let checksum = record
+        .iter()
+        .fold(0u8, |sum, b| sum.wrapping_add(*b))
+        .wrapping_neg();
+    out.push(':');
+    for b in record.iter().chain([&checksum]) {
+        write!(out, "{b:02X}").unwrap();
+    }
+    out.push('\n');
+}
+
+/// Runs of equal words are compressed with Logisim's `count*value` notation.
+fn logisim(words: &[u16]) -> String {
+    let mut entries = Vec::new();
+    let mut rest = words;
+    while let Some(&word) = rest.first() {
+        let run = rest.iter().take_while(|w| **w == word).count();
+        if run > 1 {
+            entries.push(format!("{run}*{word:x}"));
+        } else {
+            entries.push(format!("{word:x}"));
+        }
+        rest = &rest[run..];
+    }
+    let mut result = "v2.0 raw\n".to_owned();
+    for line in entries.chunks(8) {
+        result += &line.join(" ");
+        result.push('\n');
+    }
+    result
+}
+
+impl FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "hack" => OutputFormat::Hack,
+            "bin-le" => OutputFormat::BinLe,
+            "bin-be" => OutputFormat::BinBe,
+            "ihex" => OutputFormat::IntelHex,
+            "readmemh" => OutputFormat::ReadMemH,
+            "readmemb" => OutputFormat::ReadMemB,
+            "logisim" => OutputFormat::Logisim,
+            _ => {
+                return Err(format!(
+                    "unknown format `{s}`, expected one of \
+                    hack, bin-le, bin-be, ihex, readmemh, readmemb, logisim"
+                ))
+            }
+        })
+    }
+}
@@ -0,0 +1,381 @@
+use crate::{
+    error::{AsmError, ErrorKind, Field, Location},
+    instruction::{AValue, Dest, Instruction, Jump, Operator, MAX_A_VALUE},
+    macros::{rename_local_labels, split_arguments, Macro},
+};
+
+use std::{
+    collections::HashMap,
+    fs,
+    ops::Range,
+    path::{Path, PathBuf},
+    rc::Rc,
+    str::FromStr,
+};
+
+/// An instruction together with the place it was written.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Statement {
+    pub instruction: Instruction,
+    /// File the instruction was written in, differs from the assembled file
+    /// for included files and macros defined there
+    pub file: PathBuf,
+    /// Inside the macro body for instructions generated by a macro
+    pub location: Location,
+    /// Line of the assembled file this statement stems from, i.e. the
+    /// `.include` directive or the macro invocation
+    pub top_level_line: usize,
+    /// Last whole-line `//` comment above the statement, like the VM command
+    /// the VM translator writes before the code generated for it. Taken from
+    /// the invocation for instructions generated by a macro.
+    pub comment: Option<String>,
+}
+
+/// Parses every line of `source`, reporting all malformed lines at once.
+/// `file` is used to label the errors and to find included files.
+pub fn parse(file: &Path, source: &str) -> Result<Vec<Statement>, Vec<AsmError>> {
+    let mut errors = Vec::new();
+    let statements = parse_lines(file, source, &mut errors);
+    if errors.is_empty() {
+        Ok(statements)
+    } else {
+        Err(errors)
+    }
+}
+
+pub(crate) fn parse_lines(file: &Path, source: &str, errors: &mut Vec<AsmError>) -> Vec<Statement> {
+    let mut parser = Parser {
+        errors,
+        macros: HashMap::new(),
+        include_stack: vec![canonical(file)],
+        expansions: 0,
+        statements: Vec::new(),
+    };
+    parser.parse_file(file, source, None);
+    parser.statements
+}
+
+/// Macro expansions nested deeper than this are assumed to be recursive
+const MAX_EXPANSION_DEPTH: usize = 64;
+
+struct Parser<'e> {
+    errors: &'e mut Vec<AsmError>,
+    macros: HashMap<String, Rc<Macro>>,
+    /// Files currently being included, to detect cycles
+    include_stack: Vec<PathBuf>,
+    /// Number of macro expansions so far, makes local labels unique
+    expansions: usize,
+    statements: Vec<Statement>,
+}
+
+/// Where the statements of a line stem from, shared by a macro's expansion
+#[derive(Clone, Copy)]
+struct Origin<'a> {
+    top_level_line: usize,
+    comment: Option<&'a str>,
+}
+
+impl Parser<'_> {
+    /// `top_level_line` is `None` for the assembled file itself
+    fn parse_file(&mut self, file: &Path, source: &str, top_level_line: Option<usize>) {
+        let comments = comments_above(source);
+        let mut lines = trimmed_lines(source);
+        while let Some(l) = lines.next() {
+            let top_level_line = top_level_line.unwrap_or(l.number);
+            let origin = Origin {
+                top_level_line,
+                comment: comments[l.number - 1],
+            };
+            let (directive, rest) = l
+                .text
+                .split_once(char::is_whitespace)
+                .unwrap_or((l.text, ""));
+            match directive {
+                ".macro" => match Macro::define(file, &l, rest, &mut lines) {
+                    Ok(m) => {
+                        self.macros.insert(m.name.clone(), Rc::new(m));
+                    }
+                    Err(kind) => self.errors.push(l.error(file, 0..l.text.len(), kind)),
+                },
+                ".include" => self.include(file, &l, rest.trim(), top_level_line),
+                _ => self.parse_line(file, &l, origin, 0),
+            }
+        }
+    }
+
+    fn include(&mut self, file: &Path, l: &Line, name: &str, top_level_line: usize) {
+        let error = |kind| l.error(file, 0..l.text.len(), kind);
+        let Some(name) = name.strip_prefix('"').and_then(|n| n.strip_suffix('"')) else {
+            self.errors
+                .push(error(ErrorKind::BadDirective(l.text.to_owned())));
+            return;
+        };
+        let included = file.parent().unwrap_or(Path::new("")).join(name);
+        let canonical_path = canonical(&included);
+        if self.include_stack.contains(&canonical_path) {
+            self.errors
+                .push(error(ErrorKind::IncludeCycle(name.to_owned())));
+            return;
+        }
+        match fs::read_to_string(&included) {
+            Ok(source) => {
+                self.include_stack.push(canonical_path);
+                self.parse_file(&included, &source, Some(top_level_line));
+                self.include_stack.pop();
+            }
+            Err(e) => self.errors.push(error(ErrorKind::Io(format!(
+                "couldn't include `{}`: {e}",
+                included.display()
+            )))),
+        }
+    }
+
+    fn parse_line(&mut self, file: &Path, l: &Line, origin: Origin, depth: usize) {
+        let (name, args) = l
+            .text
+            .split_once(char::is_whitespace)
+            .unwrap_or((l.text, ""));
+        if let Some(m) = self.macros.get(name).cloned() {
+            if depth >= MAX_EXPANSION_DEPTH {
+                let kind = ErrorKind::BadMacro(format!("expansion of `{name}` nested too deep"));
+                self.errors.push(l.error(file, 0..name.len(), kind));
+                return;
+            }
+            self.expand(file, l, &m, args, origin, depth);
+            return;
+        }
+        if name.starts_with('.') {
+            let kind = ErrorKind::BadDirective(l.text.to_owned());
+            self.errors.push(l.error(file, 0..name.len(), kind));
+            return;
+        }
+        match parse_instruction(l) {
+            Ok(instruction) => self.statements.push(Statement {
+                instruction,
+                file: file.to_owned(),
+                location: l.location(0..l.text.len()),
+                top_level_line: origin.top_level_line,
+                comment: origin.comment.map(str::to_owned),
+            }),
+            Err(e) => self.errors.extend(
+                e.into_iter()
+                    .map(|(range, kind)| l.error(file, range, kind)),
+            ),
+        }
+    }
+
+    fn expand(
+        &mut self,
+        file: &Path,
+        l: &Line,
+        m: &Macro,
+        args: &str,
+        origin: Origin,
+        depth: usize,
+    ) {
+        let args: Vec<&str> = split_arguments(args).collect();
+        if args.len() != m.params.len() {
+            let kind = ErrorKind::BadMacro(format!(
+                "`{}` takes {} argument(s), got {}",
+                m.name,
+                m.params.len(),
+                args.len()
+            ));
+            self.errors.push(l.error(file, 0..l.text.len(), kind));
+            return;
+        }
+        self.expansions += 1;
+        let suffix = format!("${}.{}", m.name, self.expansions);
+        let first = self.statements.len();
+        for body_line in &m.body {
+            let text = m.substitute(&body_line.text, &args);
+            let line = Line {
+                number: body_line.number,
+                column: body_line.column,
+                text: &text,
+            };
+            self.parse_line(&m.file, &line, origin, depth + 1);
+        }
+        rename_local_labels(&mut self.statements[first..], &suffix);
+    }
+}
+
+/// For every line of `source`, the text of the last line before it that only
+/// holds a `//` comment
+fn comments_above(source: &str) -> Vec<Option<&str>> {
+    let mut comment = None;
+    source
+        .lines()
+        .map(|l| {
+            let above = comment;
+            if let Some(text) = l.trim().strip_prefix("//") {
+                comment = Some(text.trim());
+            }
+            above
+        })
+        .collect()
+}
+
+/// Path used to recognize a file that is already being included
+fn canonical(file: &Path) -> PathBuf {
+    file.canonicalize().unwrap_or_else(|_| file.to_owned())
+}
+
+/// Errors are given as byte ranges into `l.text`
+fn parse_instruction(l: &Line) -> Result<Instruction, Vec<(Range<usize>, ErrorKind)>> {
+    if let Some(sym_name) = l.text.strip_prefix('(') {
+        let Some(sym_name) = sym_name.strip_suffix(')') else {
+            let kind = ErrorKind::UnclosedLabel(l.text.to_owned());
+            return Err(vec![(0..l.text.len(), kind)]);
+        };
+        return Ok(Instruction::Label(sym_name.to_owned()));
+    }
+
+    if let Some(a_expr) = l.text.strip_prefix('@') {
+        let value = parse_a_value(a_expr).map_err(|kind| vec![(1..l.text.len(), kind)])?;
+        return Ok(Instruction::A(value));
+    }
+
+    // C-instruction
+    let mut errors = Vec::new();
+    let comp_start = l.text.find('=').map_or(0, |i| i + 1);
+    let comp_end = l.text.find(';').unwrap_or(l.text.len());
+    let dest = if comp_start > 0 {
+        field(l, Field::Dest, 0..comp_start - 1, &mut errors)
+    } else {
+        Some(Dest::Null)
+    };
+    let jump = if comp_end < l.text.len() {
+        field(l, Field::Jump, comp_end + 1..l.text.len(), &mut errors)
+    } else {
+        Some(Jump::Null)
+    };
+    let comp = field(
+        l,
+        Field::Comp,
+        comp_start..comp_end.max(comp_start),
+        &mut errors,
+    );
+    match (dest, comp, jump) {
+        (Some(dest), Some(comp), Some(jump)) => Ok(Instruction::C { dest, comp, jump }),
+        _ => Err(errors),
+    }
+}
+
+/// Parses operands like `42`, `0x4000`, `0b1010`, `'A'` and `SCREEN`, which
+/// may be combined with `+` and `-`.
+fn parse_a_value(text: &str) -> Result<AValue, ErrorKind> {
+    let mut value: Option<AValue> = None;
+    let mut operator = Operator::Plus;
+    let mut start = 0;
+    let mut in_char = false;
+    for (i, c) in text.char_indices().chain([(text.len(), '+')]) {
+        match c {
+            '\'' => in_char = !in_char,
+            '+' | '-' if !in_char => {
+                let operand = parse_operand(text[start..i].trim(), text)?;
+                value = Some(match value {
+                    None => operand,
+                    Some(lhs) => AValue::Sum(Box::new(lhs), operator, Box::new(operand)),
+                });
+                operator = if c == '+' {
+                    Operator::Plus
+                } else {
+                    Operator::Minus
+                };
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    Ok(value.expect("At least one operand is parsed"))
+}
+
+/// Single operand of an A-instruction, `text` is the whole operand for errors
+pub(crate) fn parse_operand(operand: &str, text: &str) -> Result<AValue, ErrorKind> {
+    let bad_literal = || ErrorKind::BadLiteral(text.to_owned());
+    let value = if let Some(c) = operand.strip_prefix('\'') {
+        let mut chars = c.chars();
+        match (chars.next(), chars.as_str()) {
+            (Some(c), "'") => c as u32,
+            _ => return Err(bad_literal()),
+        }
+    } else if let Some(hex) = operand.strip_prefix("0x") {
+        u32::from_str_radix(hex, 16).map_err(|_| bad_literal())?
+    } else if let Some(bin) = operand.strip_prefix("0b") {
+        u32::from_str_radix(bin, 2).map_err(|_| bad_literal())?
+    } else if operand.starts_with(|c: char| c.is_ascii_digit()) {
+        operand.parse().map_err(|_| bad_literal())?
+    } else if operand.is_empty() {
+        return Err(bad_literal());
+    } else {
+        return Ok(AValue::Symbol(operand.to_owned()));
+    };
+    match u16::try_from(value) {
+        Ok(value) if value <= MAX_A_VALUE => Ok(AValue::Literal(value)),
+        _ => Err(ErrorKind::OutOfRange(value.into())),
+    }
+}
+
+/// Parses the part of a C-instruction at `range`, recording unknown mnemonics
+fn field<T: FromStr>(
+    l: &Line,
+    field: Field,
+    range: Range<usize>,
+    errors: &mut Vec<(Range<usize>, ErrorKind)>,
+) -> Option<T> {
+    let text = &l.text[range.clone()];
+    text.parse().ok().or_else(|| {
+        let text = text.to_owned();
+        errors.push((range, ErrorKind::UnknownMnemonic { field, text }));
+        None
+    })
+}
+
+/// A non-empty source line with comment and surrounding whitespace removed.
+pub(crate) struct Line<'a> {
+    /// 1-based line number
+    pub number: usize,
+    /// 1-based column at which `text` starts
+    pub column: usize,
+    pub text: &'a str,
+}
+
+impl Line<'_> {
+    /// Location of the byte `range` of `self.text`
+    pub fn location(&self, range: Range<usize>) -> Location {
+        Location {
+            line: self.number,
+            columns: self.column + range.start..self.column + range.end.max(range.start + 1),
+        }
+    }
+
+    /// Error covering the byte `range` of `self.text`
+    pub fn error(&self, file: &Path, range: Range<usize>, kind: ErrorKind) -> AsmError {
+        AsmError {
+            file: file.to_owned(),
+            location: Some(self.location(range)),
+            kind,
+        }
+    }
+}
+
+pub(crate) fn trimmed_lines(s: &str) -> impl Iterator<Item = Line<'_>> {
+    s.lines().enumerate().filter_map(|(i, l)| {
+        let content = strip_comment(l);
+        let text = content.trim();
+        (!text.is_empty()).then(|| Line {
+            number: i + 1,
+            column: content.len() - content.trim_start().len() + 1,
+            text,
+        })
+    })
+}
+
+fn strip_comment(s: &str) -> &str {
+    if let Some((content, _comment)) = s.split_once("//") {
+        content
+    } else {
+        s
+    }
+}
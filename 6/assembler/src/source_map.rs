@@ -0,0 +1,114 @@
+use crate::{instruction::Instruction, Program};
+
+use std::{fmt::Write, str::FromStr};
+
+/// File formats of the map from ROM addresses back to the source.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SourceMapFormat {
+    /// Array with one object per ROM address
+    Json,
+    /// Tab-separated values with a header row
+    Tsv,
+}
+
+/// File, line, enclosing label and comment of every ROM address.
+///
+/// Labels containing `$` are local to a VM function and don't end the
+/// enclosing label, like in [`size_report`](crate::size_report). The comment
+/// is the last whole-line comment above the instruction, for code from the VM
+/// translator that's the VM command it was generated for.
+pub fn source_map(program: &Program, format: SourceMapFormat) -> String {
+    let mut label = None;
+    let mut rows = Vec::new();
+    for (address, word, s) in program.rom() {
+        match &s.instruction {
+            Instruction::Label(name) if !name.contains('$') => label = Some(name.as_str()),
+            _ => {}
+        }
+        if word.is_some() {
+            rows.push((address, s, label));
+        }
+    }
+    let mut result = String::new();
+    match format {
+        SourceMapFormat::Json => {
+            result.push('[');
+            for (i, (address, s, label)) in rows.into_iter().enumerate() {
+                let separator = if i == 0 { "" } else { "," };
+                write!(
+                    result,
+                    "{separator}\n  {{\"address\": {address}, \"file\": {}, \"line\": {}, \
+                    \"label\": {}, \"comment\": {}}}",
+                    json_string(&s.file.to_string_lossy()),
+                    s.location.line,
+                    label.map_or("null".to_owned(), json_string),
+                    s.comment.as_deref().map_or("null".to_owned(), json_string),
+                )
+                .unwrap();
+            }
+            result += "\n]\n";
+        }
+        SourceMapFormat::Tsv => {
+            result += "address\tfile\tline\tlabel\tcomment\n";
+            for (address, s, label) in rows {
+                writeln!(
+                    result,
+                    "{address}\t{}\t{}\t{}\t{}",
+                    tsv_field(&s.file.to_string_lossy()),
+                    s.location.line,
+                    tsv_field(label.unwrap_or_default()),
+                    tsv_field(s.comment.as_deref().unwrap_or_default()),
+                )
+                .unwrap();
+            }
+        }
+    }
+    result
+}
+
+fn json_string(s: &str) -> String {
+    let mut result = String::from('"');
+    for c in s.chars() {
+        match c {
+            '"' => result += "\\\"",
+            '\\' => result += "\\\\",
+            '\n' => result += "\\n",
+            '\t' => result += "\\t",
+            c if c.is_control() => write!(result, "\\u{:04x}", c as u32).unwrap(),
+            c => result.push(c),
+        }
+    }
+    result.push('"');
+    result
+}
+
+/// Tabs would start a new column, they can't occur in a field
+fn tsv_field(s: &str) -> String {
+    s.replace('\t', " ")
+}
+
+impl FromStr for SourceMapFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "json" => SourceMapFormat::Json,
+            "tsv" => SourceMapFormat::Tsv,
+            _ => {
+                return Err(format!(
+                    "unknown source map format `{s}`, expected json or tsv"
+                ))
+            }
+        })
+    }
+}
+
+impl SourceMapFormat {
+    /// Extension of the written source map, e.g. `Prog.map.json`
+    pub fn extension(self) -> &'static str {
+        match self {
+            SourceMapFormat::Json => "map.json",
+            SourceMapFormat::Tsv => "map.tsv",
+        }
+    }
+}
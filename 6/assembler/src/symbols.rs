@@ -0,0 +1,223 @@
+use crate::{
+    error::{AsmError, ErrorKind, Location},
+    instruction::AValue,
+    instruction::Instruction,
+    output::ROM_SIZE,
+    parser::{parse_operand, trimmed_lines, Statement},
+};
+
+use std::{
+    collections::{hash_map::Entry, HashMap},
+    path::Path,
+};
+
+/// Start of the screen memory map, variables have to be allocated below it
+pub(crate) const SCREEN: u16 = 16384;
+
+/// Addresses of labels, predefined symbols and variables.
+#[derive(Debug, Clone)]
+pub struct SymbolTable {
+    symbols: HashMap<String, Symbol>,
+    last_symbol_address: u16,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Symbol {
+    pub address: u16,
+    pub kind: SymbolKind,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum SymbolKind {
+    /// Built into the Hack platform or given with `--define`
+    Predefined,
+    /// ROM address of a `(label)`
+    Label,
+    /// RAM address allocated for an unknown symbol
+    Variable,
+}
+
+impl SymbolTable {
+    pub fn predefined() -> Self {
+        let symbols = [
+            ("R0", 0),
+            ("R1", 1),
+            ("R2", 2),
+            ("R3", 3),
+            ("R4", 4),
+            ("R5", 5),
+            ("R6", 6),
+            ("R7", 7),
+            ("R8", 8),
+            ("R9", 9),
+            ("R10", 10),
+            ("R11", 11),
+            ("R12", 12),
+            ("R13", 13),
+            ("R14", 14),
+            ("R15", 15),
+            ("SP", 0),
+            ("LCL", 1),
+            ("ARG", 2),
+            ("THIS", 3),
+            ("THAT", 4),
+            ("SCREEN", 16384),
+            ("KBD", 24576),
+        ];
+        SymbolTable {
+            symbols: symbols
+                .map(|(s, address)| {
+                    let kind = SymbolKind::Predefined;
+                    (s.to_owned(), Symbol { address, kind })
+                })
+                .into(),
+            last_symbol_address: 15,
+        }
+    }
+
+    /// Adds `name` as a predefined symbol, e.g. for a memory mapped peripheral.
+    /// A symbol can't be defined twice.
+    pub fn define(&mut self, name: &str, address: u16) -> Result<(), ErrorKind> {
+        let kind = SymbolKind::Predefined;
+        match self.symbols.entry(name.to_owned()) {
+            Entry::Occupied(_) => Err(ErrorKind::RedefinedSymbol(name.to_owned())),
+            Entry::Vacant(entry) => {
+                entry.insert(Symbol { address, kind });
+                Ok(())
+            }
+        }
+    }
+
+    /// Defines a symbol given as `NAME=VALUE`, the value is written like an
+    /// A-instruction literal
+    pub fn define_from_str(&mut self, definition: &str) -> Result<(), ErrorKind> {
+        let bad = || ErrorKind::BadDefinition(definition.to_owned());
+        let (name, value) = definition.split_once('=').ok_or_else(bad)?;
+        let (name, value) = (name.trim(), value.trim());
+        if name.is_empty() || name.starts_with(|c: char| c.is_ascii_digit()) {
+            return Err(bad());
+        }
+        match parse_operand(value, value)? {
+            AValue::Literal(address) => self.define(name, address),
+            _ => Err(ErrorKind::BadLiteral(value.to_owned())),
+        }
+    }
+
+    /// Defines every `NAME=VALUE` line of a symbol file, `//` starts a comment.
+    pub fn load_definitions(&mut self, file: &Path, source: &str) -> Result<(), Vec<AsmError>> {
+        let errors: Vec<_> = trimmed_lines(source)
+            .filter_map(|l| {
+                let kind = self.define_from_str(l.text).err()?;
+                Some(l.error(file, 0..l.text.len(), kind))
+            })
+            .collect();
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+
+    /// Adds the ROM address of every label in `statements`
+    pub fn first_pass(mut self, statements: &[Statement]) -> Result<Self, Vec<AsmError>> {
+        let mut errors = Vec::new();
+        self.add_labels(statements, &mut errors);
+        if errors.is_empty() {
+            Ok(self)
+        } else {
+            Err(errors)
+        }
+    }
+
+    pub(crate) fn add_labels(&mut self, statements: &[Statement], errors: &mut Vec<AsmError>) {
+        // Line on which each label of this file was defined
+        let mut defined_on = HashMap::new();
+        let mut byte_offset: usize = 0;
+        for s in statements {
+            if let Instruction::Label(sym_name) = &s.instruction {
+                match defined_on.entry(sym_name) {
+                    Entry::Occupied(first) => {
+                        let Location { line, columns } = s.location.clone();
+                        errors.push(AsmError {
+                            file: s.file.clone(),
+                            location: Some(Location {
+                                line,
+                                columns: columns.start + 1..columns.end - 1,
+                            }),
+                            kind: ErrorKind::DuplicateLabel {
+                                name: sym_name.clone(),
+                                first_line: *first.get(),
+                            },
+                        });
+                    }
+                    Entry::Vacant(v) => {
+                        v.insert(s.location.line);
+                        // Labels further out are left undefined, the program
+                        // doesn't fit into the ROM anyway
+                        if byte_offset <= ROM_SIZE {
+                            let address = u16::try_from(byte_offset).expect("Within the ROM");
+                            self.add_label(s, sym_name, address, errors);
+                        }
+                    }
+                }
+            } else {
+                byte_offset += 1;
+            }
+        }
+    }
+
+    fn add_label(
+        &mut self,
+        s: &Statement,
+        sym_name: &str,
+        address: u16,
+        errors: &mut Vec<AsmError>,
+    ) {
+        match self.symbols.entry(sym_name.to_owned()) {
+            Entry::Occupied(_) => errors.push(AsmError {
+                file: s.file.clone(),
+                location: Some(s.location.clone()),
+                kind: ErrorKind::RedefinedSymbol(sym_name.to_owned()),
+            }),
+            Entry::Vacant(v) => {
+                let kind = SymbolKind::Label;
+                v.insert(Symbol { address, kind });
+            }
+        }
+    }
+
+    pub fn get(&self, name: &str) -> Option<u16> {
+        self.symbols.get(name).map(|s| s.address)
+    }
+
+    pub fn kind(&self, name: &str) -> Option<SymbolKind> {
+        self.symbols.get(name).map(|s| s.kind)
+    }
+
+    /// Address of `name`, allocating a new variable for unknown symbols, or
+    /// an error if the address space has no room for another one
+    pub fn resolve(&mut self, name: &str) -> Result<u16, ErrorKind> {
+        if let Some(address) = self.get(name) {
+            return Ok(address);
+        }
+        self.last_symbol_address = self
+            .last_symbol_address
+            .checked_add(1)
+            .ok_or_else(|| ErrorKind::RamOverflow(name.to_owned()))?;
+        let symbol = Symbol {
+            address: self.last_symbol_address,
+            kind: SymbolKind::Variable,
+        };
+        self.symbols.insert(name.to_owned(), symbol);
+        Ok(self.last_symbol_address)
+    }
+
+    /// Address of the most recently allocated variable, 15 if there are none
+    pub(crate) fn last_variable_address(&self) -> u16 {
+        self.last_symbol_address
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, Symbol)> {
+        self.symbols.iter().map(|(name, s)| (name.as_str(), *s))
+    }
+}
@@ -0,0 +1,35 @@
+// Exercises the assembler's `.include` and `.macro` directives.
+// Computes R2 = R0 + R1 by passing both values over the stack.
+
+.include "Stack.asm"
+
+// Busy waits `n` iterations, the loop label is local to each expansion
+.macro DELAY n
+  @\n
+  D=A
+(WAIT)
+  D=D-1
+  @WAIT
+  D;JGT
+.endm
+
+  SET SP, 256
+  @R0
+  D=M
+  PUSH_D
+  @R1
+  D=M
+  PUSH_D
+  DELAY 3
+  DELAY 5
+  POP_D
+  @R13
+  M=D
+  POP_D
+  @R13
+  D=D+M
+  @R2
+  M=D
+(END)
+  @END
+  0;JMP
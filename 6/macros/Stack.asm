@@ -0,0 +1,25 @@
+// Stack idioms shared by hand-written Hack programs.
+// Included by Macros.asm.
+
+// Pushes D on the stack
+.macro PUSH_D
+  @SP
+  M=M+1
+  A=M-1
+  M=D
+.endm
+
+// Pops the topmost stack element into D
+.macro POP_D
+  @SP
+  AM=M-1
+  D=M
+.endm
+
+// RAM[addr] = value
+.macro SET addr, value
+  @\value
+  D=A
+  @\addr
+  M=D
+.endm
@@ -11,8 +11,6 @@ fn main() {
 
 #[cfg(test)]
 mod test {
-    use std::process::{Command, Stdio};
-
     use super::*;
 
     #[test]
@@ -45,17 +43,9 @@ mod test {
         let cargo_root = Path::new(env!("CARGO_MANIFEST_DIR"));
         let vm_file = cargo_root.join(vm_file);
         vm_translate(&vm_file);
-        assert!(
-            Command::new("bash")
-                .arg("../../../tools/CPUEmulator.sh")
-                .arg(vm_file.with_extension("tst"))
-                .current_dir(cargo_root)
-                .stdout(Stdio::null())
-                .status()
-                .expect("Failed to run CPUEmulator")
-                .success(),
-            "Bad status when running CPUEmulator"
-        );
+        if let Err(e) = emulator::run_script(&vm_file.with_extension("tst")) {
+            panic!("{e}");
+        }
     }
 }
 
@@ -8,19 +8,18 @@ pub fn push_to(a_expr: impl Display) -> String {
     format!("@{a_expr}\nD=M\n{}", push_d())
 }
 
-pub fn compare_command(cmp: &str, jmp_idx: &mut i32) -> String {
-    *jmp_idx += 1;
+pub fn compare_command(cmp: &str, true_label: &str) -> String {
     format!(
         r#"{pop_d}
 {peek}
 D=M-D
 M=-1
-@TRUE{jmp_idx}
+@{true_label}
 D;{cmp}
 @SP
 A=M-1
 M=0
-(TRUE{jmp_idx})"#,
+({true_label})"#,
         pop_d = pop_d(),
         peek = peek()
     )
@@ -0,0 +1,77 @@
+use std::{
+    fmt::{self, Display},
+    path::PathBuf,
+};
+
+/// A problem found while translating, pointing back at the offending VM
+/// file and line.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VmError {
+    pub file: PathBuf,
+    /// `None` if the error is not tied to a line, e.g. an unreadable file
+    pub line: Option<usize>,
+    pub kind: ErrorKind,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ErrorKind {
+    /// A line that isn't a valid VM command, with the reason
+    BadCommand {
+        text: String,
+        reason: &'static str,
+    },
+    /// A directory without any `*.vm` file
+    NoVmFiles,
+    /// A file without a UTF-8 stem to name its statics and labels after
+    BadFileName,
+    /// Two VM commands translate to the same label, e.g. functions with the
+    /// same name in two files
+    DuplicateLabel {
+        name: String,
+        first_file: PathBuf,
+        first_line: usize,
+    },
+    /// A call of a function no file defines
+    UnresolvedFunction(String),
+    /// The bootstrap calls a function no file defines
+    MissingEntry(String),
+    Io(String),
+}
+
+impl Display for VmError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:", self.file.display())?;
+        if let Some(line) = self.line {
+            write!(f, "{line}:")?;
+        }
+        write!(f, " error: {}", self.kind)
+    }
+}
+
+impl Display for ErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ErrorKind::BadCommand { text, reason } => write!(f, "{reason} in `{text}`"),
+            ErrorKind::NoVmFiles => f.write_str("no `*.vm` files in directory"),
+            ErrorKind::BadFileName => f.write_str("expected a `*.vm` file with a UTF-8 name"),
+            ErrorKind::DuplicateLabel {
+                name,
+                first_file,
+                first_line,
+            } => write!(
+                f,
+                "label `{name}` already defined at {}:{first_line}",
+                first_file.display()
+            ),
+            ErrorKind::UnresolvedFunction(name) => {
+                write!(f, "function `{name}` is called but not defined")
+            }
+            ErrorKind::MissingEntry(name) => {
+                write!(f, "entry function `{name}` is not defined")
+            }
+            ErrorKind::Io(e) => f.write_str(e),
+        }
+    }
+}
+
+impl std::error::Error for VmError {}
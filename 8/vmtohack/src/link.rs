@@ -0,0 +1,145 @@
+use crate::{
+    asm_generators::call_asm,
+    error::{ErrorKind, VmError},
+};
+
+use std::{
+    collections::{HashMap, HashSet},
+    path::{Path, PathBuf},
+};
+
+/// A translated `*.vm` file, or an `*.asm` fragment read back.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Module {
+    pub asm: String,
+    /// Names of the functions it defines
+    pub functions: Vec<String>,
+    /// Labels in the assembly code with the line they come from
+    pub labels: Vec<(String, usize)>,
+    /// Functions it calls with the line of the call
+    pub calls: Vec<(String, usize)>,
+}
+
+impl Module {
+    /// Reads back the translation of a `*.vm` file, which has every VM
+    /// command as a comment before its code. Lines are those of `asm`.
+    pub fn from_fragment(asm: String) -> Self {
+        let mut functions = Vec::new();
+        let mut labels = Vec::new();
+        let mut calls = Vec::new();
+        for (i, line) in asm.lines().enumerate() {
+            if let Some(label) = defined_labels(line).next() {
+                labels.push((label.to_owned(), i + 1));
+            }
+            let command = line.strip_prefix("// ").unwrap_or_default();
+            match command.split_whitespace().collect::<Vec<_>>()[..] {
+                ["function", name, _] => functions.push(name.to_owned()),
+                ["call", name, _] => calls.push((name.to_owned(), i + 1)),
+                _ => {}
+            }
+        }
+        Module {
+            asm,
+            functions,
+            labels,
+            calls,
+        }
+    }
+}
+
+/// Names of the `(LABEL)` lines of assembly code
+pub fn defined_labels(asm: &str) -> impl Iterator<Item = &str> {
+    asm.lines()
+        .filter_map(|line| line.strip_prefix('(')?.split(')').next())
+}
+
+/// Sets SP and calls `entry`, halting in a self-loop if it returns. Its
+/// labels are not ones the translation of a `*.vm` file can have.
+pub fn bootstrap(entry: &str, initial_sp: u16) -> String {
+    format!(
+        "@{initial_sp}\nD=A\n@SP\nM=D\n{}\n(bootstrap$$END)\n@bootstrap$$END\n0;JMP\n",
+        call_asm(entry, 0, "bootstrap$$ret")
+    )
+}
+
+/// Joins the modules in the given order into the code of `program`, starting
+/// with a bootstrap calling `entry` if it is given. Labels defined twice are
+/// errors.
+pub fn link(
+    program: &Path,
+    modules: &[(PathBuf, Module)],
+    entry: Option<(&str, u16)>,
+) -> Result<String, Vec<VmError>> {
+    // Fragments are arbitrary assembly code, which may define the labels of
+    // the bootstrap
+    let bootstrap = entry.map(|(entry, initial_sp)| {
+        let module = Module::from_fragment(bootstrap(entry, initial_sp));
+        (program.to_owned(), module)
+    });
+    let modules: Vec<(PathBuf, Module)> = bootstrap.into_iter().chain(modules.to_vec()).collect();
+    let mut errors = duplicate_labels(&modules);
+    let functions: HashSet<&str> = modules
+        .iter()
+        .flat_map(|(_, module)| &module.functions)
+        .map(String::as_str)
+        .collect();
+    if let Some((entry, _)) = entry.filter(|(entry, _)| !functions.contains(entry)) {
+        errors.push(VmError {
+            file: program.to_owned(),
+            line: None,
+            kind: ErrorKind::MissingEntry(entry.to_owned()),
+        });
+    }
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+    Ok(modules.into_iter().map(|(_, module)| module.asm).collect())
+}
+
+/// Errors for calls of functions no module defines
+pub fn unresolved_calls(modules: &[(PathBuf, Module)]) -> Vec<VmError> {
+    let functions: HashSet<&str> = modules
+        .iter()
+        .flat_map(|(_, module)| &module.functions)
+        .map(String::as_str)
+        .collect();
+    let mut errors = Vec::new();
+    for (file, module) in modules {
+        for (name, line) in &module.calls {
+            if !functions.contains(name.as_str()) {
+                errors.push(VmError {
+                    file: file.clone(),
+                    line: Some(*line),
+                    kind: ErrorKind::UnresolvedFunction(name.clone()),
+                });
+            }
+        }
+    }
+    errors
+}
+
+/// Errors for labels defined more than once in the modules, at every
+/// definition after the first one
+pub fn duplicate_labels(modules: &[(PathBuf, Module)]) -> Vec<VmError> {
+    let mut first: HashMap<&String, (&PathBuf, usize)> = HashMap::new();
+    let mut errors = Vec::new();
+    for (file, module) in modules {
+        for (label, line) in &module.labels {
+            match first.get(label) {
+                Some((first_file, first_line)) => errors.push(VmError {
+                    file: file.clone(),
+                    line: Some(*line),
+                    kind: ErrorKind::DuplicateLabel {
+                        name: label.clone(),
+                        first_file: first_file.to_path_buf(),
+                        first_line: *first_line,
+                    },
+                }),
+                None => {
+                    first.insert(label, (file, *line));
+                }
+            }
+        }
+    }
+    errors
+}
@@ -1,205 +1,377 @@
 mod asm_generators;
+mod error;
+mod link;
 mod memory_location;
 
 use asm_generators::*;
+use error::{ErrorKind, VmError};
+use link::{defined_labels, duplicate_labels, link, unresolved_calls, Module};
 use memory_location::MemoryLocation;
 
 use std::{
-    env,
-    fs::{self, File},
-    io::{BufWriter, Write},
-    path::Path,
+    env, fs,
+    path::{Path, PathBuf},
+    process,
     str::FromStr,
 };
 
-fn main() {
-    let mut args = env::args();
-    if args.len() != 2 {
-        panic!("Expect single parameter to `*.vm` file.");
+const USAGE: &str = "Usage: vmtohack [--bootstrap|--no-bootstrap] [--entry FUNCTION] \
+[--sp ADDRESS] [--output FILE] <file.vm|directory|--manifest FILE>
+       vmtohack --compile-only <file.vm|directory|--manifest FILE>
+       vmtohack --link --output FILE [--no-bootstrap] [--entry FUNCTION] [--sp ADDRESS] \
+<fragment.asm>...";
+
+/// How a program is translated.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Options {
+    /// Whether the output starts with code setting SP and calling the entry
+    /// function, by default only for directories and linked fragments
+    bootstrap: Option<bool>,
+    entry: String,
+    /// SP set by the bootstrap
+    initial_sp: u16,
+    /// File listing the `*.vm` files of the program in order, one per line
+    /// and relative to it. Without it the files of a directory are sorted by
+    /// name with `Sys.vm` first.
+    manifest: Option<PathBuf>,
+    /// Where to write the program instead of next to the input
+    output: Option<PathBuf>,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            bootstrap: None,
+            entry: "Sys.init".to_owned(),
+            initial_sp: 256,
+            manifest: None,
+            output: None,
+        }
     }
-    let filename = args.next_back().unwrap();
-    compile_path(Path::new(&filename)).unwrap();
 }
 
-#[cfg(test)]
-mod test {
-    use std::process::{Command, Stdio};
+/// Prints `message` with the usage and exits with status 2
+fn usage_error(message: &str) -> ! {
+    eprintln!("error: {message}\n{USAGE}");
+    process::exit(2)
+}
 
-    use super::*;
+/// SP given on the command line, between the registers and the screen
+fn stack_pointer(text: &str) -> u16 {
+    match text.parse() {
+        Ok(sp @ 16..16384) => sp,
+        _ => usage_error(&format!("bad stack pointer `{text}`, expected 16 to 16383")),
+    }
+}
 
-    mod project7 {
-        use super::*;
-        #[test]
-        fn simple_add() {
-            test_file("../../7/StackArithmetic/SimpleAdd/SimpleAdd.vm");
+fn main() {
+    let mut args = env::args().skip(1);
+    let mut options = Options::default();
+    let mut compile_only = false;
+    let mut link_only = false;
+    let mut filenames = Vec::new();
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .unwrap_or_else(|| usage_error(&format!("missing value for `{arg}`")))
+        };
+        match arg.as_str() {
+            "--bootstrap" => options.bootstrap = Some(true),
+            "--no-bootstrap" => options.bootstrap = Some(false),
+            "--entry" => options.entry = value(),
+            "--sp" => options.initial_sp = stack_pointer(&value()),
+            "--manifest" => options.manifest = Some(value().into()),
+            "--output" => options.output = Some(value().into()),
+            "--compile-only" => compile_only = true,
+            "--link" => link_only = true,
+            _ if !arg.starts_with("--") => filenames.push(PathBuf::from(arg)),
+            _ => usage_error(&format!("unknown option `{arg}`")),
         }
-
-        #[test]
-        fn stack_test() {
-            test_file("../../7/StackArithmetic/StackTest/StackTest.vm");
+    }
+    if filenames.is_empty() && !link_only {
+        filenames.extend(options.manifest.clone());
+    }
+    let result = match &filenames[..] {
+        [_, ..] if link_only && !compile_only && options.output.is_some() => {
+            link_fragments(&filenames, &options)
         }
-
-        #[test]
-        fn basic_test() {
-            test_file("../../7/MemoryAccess/BasicTest/BasicTest.vm");
+        [path] if compile_only && !link_only => compile_fragments(path, &options).map(|_| ()),
+        [path] if !link_only => compile_path(path, &options).map(|_| ()),
+        [] => usage_error("missing file"),
+        _ if link_only && options.output.is_none() => usage_error("`--link` needs `--output`"),
+        _ => usage_error("bad combination of options and files"),
+    };
+    if let Err(errors) = result {
+        for e in &errors {
+            eprintln!("{e}");
         }
+        eprintln!("{} error(s), no output generated", errors.len());
+        process::exit(1);
+    }
+}
 
-        #[test]
-        fn pointer_test() {
-            test_file("../../7/MemoryAccess/PointerTest/PointerTest.vm");
-        }
+fn io_error(file: &Path, e: std::io::Error) -> Vec<VmError> {
+    vec![VmError {
+        file: file.to_owned(),
+        line: None,
+        kind: ErrorKind::Io(e.to_string()),
+    }]
+}
 
-        #[test]
-        fn static_test() {
-            test_file("../../7/MemoryAccess/StaticTest/StaticTest.vm");
+/// The `*.vm` files of a program in the order they are linked
+fn vm_files(path: &Path, options: &Options) -> Result<Vec<PathBuf>, Vec<VmError>> {
+    if let Some(manifest) = &options.manifest {
+        return read_manifest(manifest);
+    }
+    if path.is_file() {
+        return Ok(vec![path.to_owned()]);
+    }
+    if !path.is_dir() {
+        return Err(io_error(path, std::io::ErrorKind::NotFound.into()));
+    }
+    let mut vm_files = Vec::new();
+    for dir_entry in fs::read_dir(path).map_err(|e| io_error(path, e))? {
+        let file = dir_entry.map_err(|e| io_error(path, e))?.path();
+        if file.extension().is_some_and(|e| e == "vm") {
+            vm_files.push(file);
         }
     }
-
-    #[test]
-    fn basic_loop() {
-        test_file("../ProgramFlow/BasicLoop/BasicLoop.vm")
+    if vm_files.is_empty() {
+        return Err(vec![VmError {
+            file: path.to_owned(),
+            line: None,
+            kind: ErrorKind::NoVmFiles,
+        }]);
     }
+    vm_files.sort_by_key(|file| (!file.ends_with("Sys.vm"), file.clone()));
+    Ok(vm_files)
+}
 
-    #[test]
-    fn fibonacci_series() {
-        test_file("../ProgramFlow/FibonacciSeries/FibonacciSeries.vm")
+/// The files listed in a manifest, or every line naming a missing file
+fn read_manifest(manifest: &Path) -> Result<Vec<PathBuf>, Vec<VmError>> {
+    let text = fs::read_to_string(manifest).map_err(|e| io_error(manifest, e))?;
+    let dir = manifest.parent().unwrap_or(Path::new("."));
+    let mut vm_files = Vec::new();
+    let mut errors = Vec::new();
+    for (line, name) in trimmed_lines(&text) {
+        let file = dir.join(name);
+        if file.is_file() {
+            vm_files.push(file);
+        } else {
+            errors.push(VmError {
+                file: manifest.to_owned(),
+                line: Some(line),
+                kind: ErrorKind::Io(format!("no file `{}`", file.display())),
+            });
+        }
     }
-
-    #[test]
-    fn simple_function() {
-        test_file("../FunctionCalls/SimpleFunction/SimpleFunction.vm")
+    if vm_files.is_empty() && errors.is_empty() {
+        errors.push(VmError {
+            file: manifest.to_owned(),
+            line: None,
+            kind: ErrorKind::NoVmFiles,
+        });
     }
-
-    #[test]
-    fn fibonacci_element() {
-        test_dir("../FunctionCalls/FibonacciElement/");
+    if !errors.is_empty() {
+        return Err(errors);
     }
+    Ok(vm_files)
+}
 
-    #[test]
-    fn nested_call() {
-        test_dir("../FunctionCalls/NestedCall/");
+/// Translates every file, or returns the errors of all of them
+fn compile_files(vm_files: Vec<PathBuf>) -> Result<Vec<(PathBuf, Module)>, Vec<VmError>> {
+    let mut modules = Vec::new();
+    let mut errors = Vec::new();
+    for file in vm_files {
+        match compile_file(&file) {
+            Ok(module) => modules.push((file, module)),
+            Err(e) => errors.extend(e),
+        }
     }
-
-    #[test]
-    fn static_test() {
-        test_dir("../FunctionCalls/StaticsTest/");
+    if !errors.is_empty() {
+        return Err(errors);
     }
+    Ok(modules)
+}
 
-    fn test_file(vm_file: &str) {
-        let vm_file = Path::new(vm_file);
-        test_path(vm_file, &vm_file.with_extension("tst"));
-    }
+/// Translates a `*.vm` file into a `*.asm` file next to it, or all `*.vm`
+/// files of a directory into one `*.asm` file inside it named like the
+/// directory, or the files of a manifest given as `path` into one `*.asm`
+/// file next to it. Nothing is written if any file has errors. Calls of
+/// functions not in the program are left to the OS.
+fn compile_path(path: &Path, options: &Options) -> Result<PathBuf, Vec<VmError>> {
+    let modules = compile_files(vm_files(path, options)?)?;
+    let asm_file = match &options.output {
+        Some(output) => output.clone(),
+        None if path.is_dir() => {
+            let name = path
+                .file_name()
+                .expect("Already checked that it's a directory");
+            path.join(name).with_extension("asm")
+        }
+        None => path.with_extension("asm"),
+    };
+    let program = path.is_dir() || options.manifest.is_some();
+    let bootstrap = options.bootstrap.unwrap_or(program);
+    let asm = if bootstrap || program {
+        let entry = bootstrap.then_some((options.entry.as_str(), options.initial_sp));
+        link(path, &modules, entry)?
+    } else {
+        // A single file may call functions of files translated separately
+        let errors = duplicate_labels(&modules);
+        if !errors.is_empty() {
+            return Err(errors);
+        }
+        modules.into_iter().map(|(_, module)| module.asm).collect()
+    };
+    fs::write(&asm_file, asm).map_err(|e| io_error(&asm_file, e))?;
+    Ok(asm_file)
+}
 
-    fn test_dir(dir: &str) {
-        let dir = Path::new(dir);
-        let dir_name = dir.file_name().unwrap();
-        test_path(dir, &dir.join(dir_name).with_extension("tst"));
+/// Translates each `*.vm` file into an `*.asm` fragment next to it, to be
+/// joined by [`link_fragments`]. Only labels within a file are checked.
+fn compile_fragments(path: &Path, options: &Options) -> Result<Vec<PathBuf>, Vec<VmError>> {
+    let modules = compile_files(vm_files(path, options)?)?;
+    let errors: Vec<VmError> = modules
+        .iter()
+        .flat_map(|module| duplicate_labels(std::slice::from_ref(module)))
+        .collect();
+    if !errors.is_empty() {
+        return Err(errors);
     }
-
-    /// Compile provided vm file to asm, and check result with a `*.tst` file
-    fn test_path(path: &Path, tst_file_path: &Path) {
-        let cargo_root = Path::new(env!("CARGO_MANIFEST_DIR"));
-        let path = cargo_root.join(path);
-        compile_path(&path).unwrap();
-        assert!(
-            Command::new("bash")
-                .arg("../../../tools/CPUEmulator.sh")
-                .arg(tst_file_path)
-                .current_dir(cargo_root)
-                .stdout(Stdio::null())
-                .status()
-                .expect("Failed to run CPUEmulator")
-                .success(),
-            "Bad status when running CPUEmulator"
-        );
+    let mut fragments = Vec::new();
+    for (file, module) in modules {
+        let fragment = file.with_extension("asm");
+        fs::write(&fragment, module.asm).map_err(|e| io_error(&fragment, e))?;
+        fragments.push(fragment);
     }
+    Ok(fragments)
 }
 
-fn compile_path(path: &Path) -> std::io::Result<()> {
-    if path.is_file() {
-        let mut out = BufWriter::new(File::open(path.with_extension("asm"))?);
-        compile_file(path, &mut out);
-        Ok(())
-    } else if path.is_dir() {
-        let name = path
-            .file_name()
-            .expect("Already checked that it's a directory");
-        let asm_file = path.join(name).with_extension("asm");
-        let mut out = BufWriter::new(File::create(asm_file)?);
-        // TODO: Error when no vm file is found
-        for dir_entry in fs::read_dir(path)? {
-            let file = dir_entry?.path();
-            if file.extension().is_some_and(|e| e == "vm") {
-                compile_file(&file, &mut out)
-            }
+/// Joins `*.asm` fragments in the given order into the output file, starting
+/// with the bootstrap unless it is turned off. Every called function must be
+/// in one of the fragments.
+fn link_fragments(fragments: &[PathBuf], options: &Options) -> Result<(), Vec<VmError>> {
+    let output = options.output.as_deref().expect(USAGE);
+    let mut modules = Vec::new();
+    let mut errors = Vec::new();
+    for file in fragments {
+        match fs::read_to_string(file) {
+            Ok(asm) => modules.push((file.clone(), Module::from_fragment(asm))),
+            Err(e) => errors.extend(io_error(file, e)),
         }
-        Ok(())
-    } else {
-        Err(std::io::ErrorKind::NotFound.into())
     }
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+    let entry = options
+        .bootstrap
+        .unwrap_or(true)
+        .then_some((options.entry.as_str(), options.initial_sp));
+    let mut errors = unresolved_calls(&modules);
+    let asm = link(output, &modules, entry).map_err(|mut e| {
+        e.append(&mut errors);
+        e
+    })?;
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+    fs::write(output, asm).map_err(|e| io_error(output, e))
 }
 
-fn compile_file(vm_file: &Path, out: &mut impl Write) {
+/// Assembly code of a `*.vm` file, or every bad line of it
+fn compile_file(vm_file: &Path) -> Result<Module, Vec<VmError>> {
+    let error = |line, kind| VmError {
+        file: vm_file.to_owned(),
+        line,
+        kind,
+    };
     let module_id = vm_file
         .file_stem()
-        .unwrap_or_else(|| panic!("Expected *.vm file, got `{}`", vm_file.display()))
-        .to_str()
-        .expect("Filename has to be unicode.");
-    let asm_file = fs::read_to_string(vm_file)
-        .unwrap_or_else(|_| panic!("Couldn't read {}.", vm_file.display()));
-
-    out.write_all(
-        format!(
-            "@256\nD=A\n@SP\nM=D\n{}\n0;JMP\n",
-            call_asm(&"Sys.init", 0, "Sys.init$ret.0")
-        )
-        .as_bytes(),
-    )
-    .unwrap_or_else(|_| panic!("Error writing {module_id}"));
-    let mut jmp_idx = 0;
-    let mut return_function_idx = 0..;
-    let mut current_function = "root".to_owned();
-    let mut result = trimmed_lines(&asm_file)
-        .map(|l| {
-            let o = l
-                .parse()
-                .unwrap_or_else(|e| panic!("Error parsing `{l}`: {}", e));
+        .and_then(|stem| stem.to_str())
+        .ok_or_else(|| vec![error(None, ErrorKind::BadFileName)])?;
+    let vm_code = fs::read_to_string(vm_file).map_err(|e| {
+        vec![error(
+            None,
+            ErrorKind::Io(format!("couldn't read file: {e}")),
+        )]
+    })?;
+
+    let mut commands = Vec::new();
+    let mut errors = Vec::new();
+    for (line, text) in trimmed_lines(&vm_code) {
+        match text.parse::<VmCommand>() {
+            Ok(command) => commands.push((line, text, command)),
+            Err(reason) => errors.push(error(
+                Some(line),
+                ErrorKind::BadCommand {
+                    text: text.to_owned(),
+                    reason,
+                },
+            )),
+        }
+    }
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+
+    let mut functions = Vec::new();
+    let mut labels = Vec::new();
+    let mut calls = Vec::new();
+    // Labels are scoped by the function, or by the file before the first
+    // function. Labels of the translation are counted from 0 in each scope
+    // and set apart by `$$`, which VM names can't contain.
+    let mut scope = module_id.to_owned();
+    let mut compare_idx = 0..;
+    let mut return_idx = 0..;
+    let translated = commands
+        .into_iter()
+        .map(|(line, l, o)| {
+            let mut compare = |cmp| {
+                let n = compare_idx.next().unwrap();
+                let label = format!("{module_id}${scope}$$TRUE.{n}");
+                compare_command(cmp, &label)
+            };
             let asm = match o {
                 VmCommand::Add => pop_d() + "\n" + &peek() + "\nM=M+D",
                 VmCommand::Sub => pop_d() + "\n" + &peek() + "\nM=M-D",
                 VmCommand::Neg => peek() + "\nM=-M",
-                VmCommand::Eq => compare_command("JEQ", &mut jmp_idx),
-                VmCommand::Gt => compare_command("JGT", &mut jmp_idx),
-                VmCommand::Lt => compare_command("JLT", &mut jmp_idx),
+                VmCommand::Eq => compare("JEQ"),
+                VmCommand::Gt => compare("JGT"),
+                VmCommand::Lt => compare("JLT"),
                 VmCommand::And => pop_d() + "\n" + &peek() + "\nM=M&D",
                 VmCommand::Or => pop_d() + "\n" + &peek() + "\nM=M|D",
                 VmCommand::Not => peek() + "\nM=!M",
                 VmCommand::Push(k) => k.push(module_id),
                 VmCommand::Pop(k) => k.pop(module_id),
-                VmCommand::Label(label) => format!("({current_function}${label})"),
-                VmCommand::Goto(label) => format!("@{current_function}${label}\n0;JMP"),
-                VmCommand::IfGoto(label) => {
-                    pop_d() + &format!("\n@{current_function}${label}\nD;JNE")
-                }
+                VmCommand::Label(label) => format!("({scope}${label})"),
+                VmCommand::Goto(label) => format!("@{scope}${label}\n0;JMP"),
+                VmCommand::IfGoto(label) => pop_d() + &format!("\n@{scope}${label}\nD;JNE"),
                 VmCommand::Function(name, nvars) => {
-                    current_function = name;
-                    format!("({current_function}){}", zero_local(nvars))
+                    functions.push(name.clone());
+                    scope = name;
+                    compare_idx = 0..;
+                    return_idx = 0..;
+                    format!("({scope}){}", zero_local(nvars))
                 }
                 VmCommand::Return => return_asm(),
                 VmCommand::Call(name, nargs) => {
-                    let idx = return_function_idx.next().unwrap();
-                    let return_label = format!("{current_function}$ret.{idx}");
+                    calls.push((name.clone(), line));
+                    let return_label = format!("{scope}$$ret.{}", return_idx.next().unwrap());
                     call_asm(&name, nargs, &return_label)
                 }
             };
+            labels.extend(defined_labels(&asm).map(|label| (label.to_owned(), line)));
             format!("// {l}\n{asm}")
         })
         .collect::<Vec<String>>()
         .join("\n");
-
-    result.push('\n');
-    out.write_all(result.as_bytes())
-        .expect("Failed to write output file");
+    Ok(Module {
+        asm: translated + "\n",
+        functions,
+        labels,
+        calls,
+    })
 }
 
 enum VmCommand {
@@ -239,40 +411,55 @@ impl FromStr for VmCommand {
             "or" => VmCommand::Or,
             "not" => VmCommand::Not,
             "push" => VmCommand::Push(MemoryLocation::from(&mut parts)?),
-            "pop" => VmCommand::Pop(MemoryLocation::from(&mut parts)?),
-            "label" => VmCommand::Label(parts.next().ok_or("Missing label name")?.to_owned()),
-            "goto" => VmCommand::Goto(parts.next().ok_or("Missing goto label")?.to_owned()),
-            "if-goto" => VmCommand::IfGoto(parts.next().ok_or("Missing if-goto label")?.to_owned()),
+            "pop" => match MemoryLocation::from(&mut parts)? {
+                MemoryLocation::Constant(_) => return Err("cannot pop to constant"),
+                location => VmCommand::Pop(location),
+            },
+            "label" => VmCommand::Label(name(parts.next().ok_or("missing label name")?)?),
+            "goto" => VmCommand::Goto(name(parts.next().ok_or("missing goto label")?)?),
+            "if-goto" => VmCommand::IfGoto(name(parts.next().ok_or("missing if-goto label")?)?),
             "function" => VmCommand::Function(
-                parts.next().ok_or("Missing function name")?.to_owned(),
+                name(parts.next().ok_or("missing function name")?)?,
                 parts
                     .next()
-                    .ok_or("Missing nargs for function")?
+                    .ok_or("missing argument count")?
                     .parse()
-                    .map_err(|_| "Unable to parse nargs")?,
+                    .map_err(|_| "bad argument count")?,
             ),
             "return" => VmCommand::Return,
             "call" => VmCommand::Call(
-                parts.next().ok_or("Missing function name")?.to_owned(),
+                name(parts.next().ok_or("missing function name")?)?,
                 parts
                     .next()
-                    .ok_or("Missing nargs for function")?
+                    .ok_or("missing argument count")?
                     .parse()
-                    .map_err(|_| "Unable to parse nargs")?,
+                    .map_err(|_| "bad argument count")?,
             ),
-            _ => return Err("Unexpected expression"),
+            _ => return Err("unknown command"),
         };
-        if let Some(_) = parts.next() {
-            return Err("Spurious element after command");
+        if parts.next().is_some() {
+            return Err("unexpected text after command");
         }
         Ok(operation)
     }
 }
 
-fn trimmed_lines(s: &str) -> impl Iterator<Item = &str> {
+/// A label or function name: letters, digits, `_`, `.` and `:`, not starting
+/// with a digit
+fn name(text: &str) -> Result<String, &'static str> {
+    let valid = |c: char| c.is_ascii_alphanumeric() || "_.:".contains(c);
+    if text.starts_with(|c: char| c.is_ascii_digit()) || !text.chars().all(valid) {
+        return Err("bad name");
+    }
+    Ok(text.to_owned())
+}
+
+/// Non-empty lines without comments, with their 1-based line numbers
+fn trimmed_lines(s: &str) -> impl Iterator<Item = (usize, &str)> {
     s.lines()
-        .map(|l| strip_comment(l).trim())
-        .filter(|l| !l.is_empty())
+        .enumerate()
+        .map(|(i, l)| (i + 1, strip_comment(l).trim()))
+        .filter(|(_, l)| !l.is_empty())
 }
 
 fn strip_comment(s: &str) -> &str {
@@ -282,3 +469,398 @@ fn strip_comment(s: &str) -> &str {
         s
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    mod project7 {
+        use super::*;
+        #[test]
+        fn simple_add() {
+            test_file("../../7/StackArithmetic/SimpleAdd/SimpleAdd.vm");
+        }
+
+        #[test]
+        fn stack_test() {
+            test_file("../../7/StackArithmetic/StackTest/StackTest.vm");
+        }
+
+        #[test]
+        fn basic_test() {
+            test_file("../../7/MemoryAccess/BasicTest/BasicTest.vm");
+        }
+
+        #[test]
+        fn pointer_test() {
+            test_file("../../7/MemoryAccess/PointerTest/PointerTest.vm");
+        }
+
+        #[test]
+        fn static_test() {
+            test_file("../../7/MemoryAccess/StaticTest/StaticTest.vm");
+        }
+    }
+
+    #[test]
+    fn basic_loop() {
+        test_file("../ProgramFlow/BasicLoop/BasicLoop.vm")
+    }
+
+    #[test]
+    fn fibonacci_series() {
+        test_file("../ProgramFlow/FibonacciSeries/FibonacciSeries.vm")
+    }
+
+    #[test]
+    fn simple_function() {
+        test_file("../FunctionCalls/SimpleFunction/SimpleFunction.vm")
+    }
+
+    #[test]
+    fn fibonacci_element() {
+        test_dir("../FunctionCalls/FibonacciElement/");
+    }
+
+    #[test]
+    fn nested_call() {
+        test_dir("../FunctionCalls/NestedCall/");
+    }
+
+    #[test]
+    fn static_test() {
+        test_dir("../FunctionCalls/StaticsTest/");
+    }
+
+    #[test]
+    fn reports_every_bad_line() {
+        let dir = TempDir::new("bad-lines");
+        let vm_file = dir.write(
+            "Bad.vm",
+            "push constant 1\npush nowhere 2 // comment\n\npop constant 3\npush pointer 2\nadd 1\nfunction f x\n",
+        );
+        let asm_file = vm_file.with_extension("asm");
+        let errors = compile_path(&vm_file, &Options::default()).unwrap_err();
+        let messages: Vec<String> = errors
+            .iter()
+            .map(|e| e.to_string().replace(&dir.display().to_string(), "dir"))
+            .collect();
+        assert_eq!(
+            messages,
+            [
+                "dir/Bad.vm:2: error: unknown segment in `push nowhere 2`",
+                "dir/Bad.vm:4: error: cannot pop to constant in `pop constant 3`",
+                "dir/Bad.vm:5: error: pointer index must be 0 or 1 in `push pointer 2`",
+                "dir/Bad.vm:6: error: unexpected text after command in `add 1`",
+                "dir/Bad.vm:7: error: bad argument count in `function f x`",
+            ]
+        );
+        assert!(!asm_file.exists());
+    }
+
+    #[test]
+    fn directory_without_vm_files() {
+        let dir = TempDir::new("empty");
+        let errors = compile_path(&dir, &Options::default()).unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].kind, ErrorKind::NoVmFiles);
+        assert!(compile_path(&dir.join("Missing.vm"), &Options::default()).is_err());
+    }
+
+    #[test]
+    fn bad_file_names() {
+        let errors = compile_file(Path::new("..")).unwrap_err();
+        assert_eq!(errors[0].kind, ErrorKind::BadFileName);
+        #[cfg(unix)]
+        {
+            use std::{ffi::OsStr, os::unix::ffi::OsStrExt};
+            let file = Path::new(OsStr::from_bytes(b"Bad\xFF.vm"));
+            let errors = compile_file(file).unwrap_err();
+            assert_eq!(errors[0].kind, ErrorKind::BadFileName);
+        }
+    }
+
+    #[test]
+    fn entry_function_and_initial_sp() {
+        let dir = TempDir::new("entry");
+        let vm_file = dir.write(
+            "Harness.vm",
+            "function Harness.test 0\npush constant 7\npush constant 8\nadd\nreturn\n",
+        );
+        let mut options = Options {
+            bootstrap: Some(true),
+            entry: "Harness.test".to_owned(),
+            initial_sp: 300,
+            ..Options::default()
+        };
+        let asm_file = compile_path(&vm_file, &options).unwrap();
+        let mut computer = emulator::Computer::load(&asm_file).unwrap();
+        assert_eq!(computer.run(1000), emulator::Stop::Halted);
+        // The return value replaces the frame pushed by the bootstrap, LCL
+        // is restored
+        assert_eq!(computer.ram[..2], [301, 0]);
+        assert_eq!(computer.ram[300], 15);
+
+        options.entry = "Harness.missing".to_owned();
+        let errors = compile_path(&vm_file, &options).unwrap_err();
+        assert_eq!(
+            errors[0].kind,
+            ErrorKind::MissingEntry("Harness.missing".to_owned())
+        );
+
+        // Directories get a bootstrap calling Sys.init unless it is turned
+        // off
+        let errors = compile_path(&dir, &Options::default()).unwrap_err();
+        assert_eq!(
+            errors[0].kind,
+            ErrorKind::MissingEntry("Sys.init".to_owned())
+        );
+        options.bootstrap = Some(false);
+        let asm = fs::read_to_string(compile_path(&dir, &options).unwrap()).unwrap();
+        assert!(asm.starts_with("// function Harness.test 0\n"), "{asm}");
+    }
+
+    #[test]
+    fn labels_are_unique_across_files() {
+        let dir = TempDir::new("labels");
+        dir.write(
+            "Sys.vm",
+            "function Sys.init 0\npush constant 3\npush constant 5\nlt\npop temp 0\n\
+             call Other.f 0\npop temp 1\nlabel END\ngoto END\n",
+        );
+        dir.write(
+            "Other.vm",
+            "function Other.f 0\npush constant 5\npush constant 3\ngt\ncall Other.g 0\nand\n\
+             return\nfunction Other.g 0\npush constant 2\npush constant 3\neq\nreturn\n",
+        );
+        let asm_file = compile_path(&dir, &Options::default()).unwrap();
+        let asm = fs::read_to_string(&asm_file).unwrap();
+        for label in [
+            "Sys$Sys.init$$TRUE.0",
+            "Other$Other.f$$TRUE.0",
+            "Other$Other.g$$TRUE.0",
+        ] {
+            assert!(asm.contains(&format!("({label})")), "{label}");
+        }
+        for label in ["bootstrap$$ret", "Sys.init$$ret.0", "Other.f$$ret.0"] {
+            assert!(asm.contains(&format!("({label})")), "{label}");
+        }
+        let mut computer = emulator::Computer::load(&asm_file).unwrap();
+        computer.run(10_000);
+        assert_eq!(computer.ram[5..7], [0xFFFF, 0]);
+
+        // The same function in two files
+        dir.write(
+            "Copy.vm",
+            "// Copied from Other.vm\nfunction Other.g 0\npush constant 0\nreturn\n",
+        );
+        let errors = compile_path(&dir, &Options::default()).unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].file, dir.join("Other.vm"));
+        assert_eq!(errors[0].line, Some(8));
+        assert_eq!(
+            errors[0].kind,
+            ErrorKind::DuplicateLabel {
+                name: "Other.g".to_owned(),
+                first_file: dir.join("Copy.vm"),
+                first_line: 2,
+            }
+        );
+    }
+
+    #[test]
+    fn labels_apart_from_vm_labels() {
+        let dir = TempDir::new("vm-labels");
+        dir.write(
+            "F.vm",
+            "function F.f 0\npush constant 1\npush constant 1\neq\nlabel TRUE.0\nreturn\n",
+        );
+        dir.write(
+            "Sys.vm",
+            "function Sys.init 0\ncall F.f 0\nlabel END\ngoto END\n\
+             function bootstrap 0\nlabel END\ncall bootstrap 0\nreturn\n",
+        );
+        let asm = fs::read_to_string(compile_path(&dir, &Options::default()).unwrap()).unwrap();
+        for label in [
+            "F$F.f$$TRUE.0",
+            "F.f$TRUE.0",
+            "bootstrap$$END",
+            "bootstrap$END",
+        ] {
+            assert_eq!(asm.matches(&format!("({label})")).count(), 1, "{label}");
+        }
+
+        let vm_file = dir.write("Bad.vm", "label F.f$$TRUE.0\ncall 1f 0\n");
+        let errors = compile_file(&vm_file).unwrap_err();
+        assert_eq!(errors.len(), 2);
+
+        // Fragments may still define the labels of the bootstrap
+        let fragment = dir.write(
+            "Sys.asm",
+            "// function Sys.init 0\n(Sys.init)\n(bootstrap$$END)\n",
+        );
+        let options = Options {
+            output: Some(dir.join("Program.asm")),
+            ..Options::default()
+        };
+        let errors = link_fragments(std::slice::from_ref(&fragment), &options).unwrap_err();
+        assert_eq!((&errors[0].file, errors[0].line), (&fragment, Some(3)));
+    }
+
+    #[test]
+    fn file_order() {
+        let dir = TempDir::new("order");
+        for name in ["Main", "Sys", "Array", "Zebra"] {
+            let code = format!("function {name}.f 0\npush constant 0\nreturn\n");
+            dir.write(&format!("{name}.vm"), &code);
+        }
+        let names = |files: Vec<PathBuf>| -> Vec<String> {
+            files
+                .iter()
+                .map(|f| f.file_stem().unwrap().to_string_lossy().into_owned())
+                .collect()
+        };
+        let options = Options::default();
+        let files = vm_files(&dir, &options).unwrap();
+        assert_eq!(names(files), ["Sys", "Array", "Main", "Zebra"]);
+
+        let manifest = dir.write("program.txt", "// Link order\nZebra.vm\n\nMain.vm\n");
+        let options = Options {
+            manifest: Some(manifest.clone()),
+            ..Options::default()
+        };
+        let files = vm_files(&dir, &options).unwrap();
+        assert_eq!(names(files), ["Zebra", "Main"]);
+        // A manifest is a program like a directory, written next to it
+        let errors = compile_path(&manifest, &options).unwrap_err();
+        assert_eq!(
+            errors[0].kind,
+            ErrorKind::MissingEntry("Sys.init".to_owned())
+        );
+        let options = Options {
+            bootstrap: Some(false),
+            ..options
+        };
+        let asm_file = compile_path(&manifest, &options).unwrap();
+        assert_eq!(asm_file, dir.join("program.asm"));
+
+        dir.write("program.txt", "Main.vm\nMissing.vm\n");
+        let errors = vm_files(&dir, &options).unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert_eq!((&errors[0].file, errors[0].line), (&manifest, Some(2)));
+    }
+
+    #[test]
+    fn separate_compilation() {
+        let dir = TempDir::new("separate");
+        dir.write(
+            "Sys.vm",
+            "function Sys.init 0\npush constant 4\ncall Math.double 1\npop temp 0\n\
+             label END\ngoto END\n",
+        );
+        // Functions not in a directory may come from the OS
+        compile_path(&dir, &Options::default()).unwrap();
+        dir.write(
+            "Math.vm",
+            "function Math.double 0\npush argument 0\npush argument 0\nadd\nreturn\n",
+        );
+        let fragments = compile_fragments(&dir, &Options::default()).unwrap();
+        assert_eq!(fragments, [dir.join("Sys.asm"), dir.join("Math.asm")]);
+        let sys = fs::read_to_string(&fragments[0]).unwrap();
+        assert!(sys.starts_with("// function Sys.init 0\n"), "{sys}");
+
+        let output = dir.join("Program.asm");
+        let options = Options {
+            output: Some(output.clone()),
+            ..Options::default()
+        };
+        link_fragments(&fragments, &options).unwrap();
+        let mut computer = emulator::Computer::load(&output).unwrap();
+        computer.run(1000);
+        assert_eq!(computer.ram[5], 8);
+
+        // Errors point at lines of the fragments
+        let errors = link_fragments(&fragments[..1], &options).unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert_eq!((&errors[0].file, errors[0].line), (&fragments[0], Some(10)));
+        assert_eq!(
+            errors[0].kind,
+            ErrorKind::UnresolvedFunction("Math.double".to_owned())
+        );
+        let errors = link_fragments(&fragments[1..], &options).unwrap_err();
+        assert_eq!(
+            errors[0].kind,
+            ErrorKind::MissingEntry("Sys.init".to_owned())
+        );
+
+        // Linking a fragment twice defines its labels twice
+        let twice = [
+            fragments[0].clone(),
+            fragments[1].clone(),
+            fragments[1].clone(),
+        ];
+        let errors = link_fragments(&twice, &options).unwrap_err();
+        assert_eq!(
+            errors[0].kind,
+            ErrorKind::DuplicateLabel {
+                name: "Math.double".to_owned(),
+                first_file: fragments[1].clone(),
+                first_line: 2,
+            }
+        );
+    }
+
+    /// A fresh directory for the files of a test, removed afterwards
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new(name: &str) -> Self {
+            let dir = env::temp_dir().join(format!("vmtohack-{name}-{}", process::id()));
+            let _ = fs::remove_dir_all(&dir);
+            fs::create_dir_all(&dir).unwrap();
+            TempDir(dir)
+        }
+
+        /// Writes a file into the directory, returning its path
+        fn write(&self, name: &str, contents: &str) -> PathBuf {
+            let file = self.0.join(name);
+            fs::write(&file, contents).unwrap();
+            file
+        }
+    }
+
+    impl std::ops::Deref for TempDir {
+        type Target = Path;
+
+        fn deref(&self) -> &Path {
+            &self.0
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn test_file(vm_file: &str) {
+        let vm_file = Path::new(vm_file);
+        test_path(vm_file, &vm_file.with_extension("tst"));
+    }
+
+    fn test_dir(dir: &str) {
+        let dir = Path::new(dir);
+        let dir_name = dir.file_name().unwrap();
+        test_path(dir, &dir.join(dir_name).with_extension("tst"));
+    }
+
+    /// Compile provided vm file to asm, and check result with a `*.tst` file
+    fn test_path(path: &Path, tst_file_path: &Path) {
+        let cargo_root = Path::new(env!("CARGO_MANIFEST_DIR"));
+        let path = cargo_root.join(path);
+        compile_path(&path, &Options::default()).unwrap();
+        if let Err(e) = emulator::run_script(&cargo_root.join(tst_file_path)) {
+            panic!("{e}");
+        }
+    }
+}
@@ -49,22 +49,25 @@ fn pointer_name(pointer_id: &usize) -> &str {
 
 impl MemoryLocation {
     pub fn from<'a>(parts: &mut impl Iterator<Item = &'a str>) -> Result<Self, &'static str> {
-        let kind = parts.next().ok_or("Missing kind")?;
+        let kind = parts.next().ok_or("missing segment")?;
         let number: usize = parts
             .next()
-            .ok_or("Missing number")?
+            .ok_or("missing index")?
             .parse()
-            .map_err(|_| "Could not parse number")?;
+            .map_err(|_| "bad index")?;
         Ok(match kind {
+            "constant" if number > 0x7FFF => return Err("constant doesn't fit into 15 bits"),
             "constant" => MemoryLocation::Constant(number),
             "local" => MemoryLocation::Local(number),
             "argument" => MemoryLocation::Argument(number),
             "this" => MemoryLocation::This(number),
             "that" => MemoryLocation::That(number),
+            "temp" if number > 7 => return Err("temp index must be 0 to 7"),
             "temp" => MemoryLocation::Temp(number),
+            "pointer" if number > 1 => return Err("pointer index must be 0 or 1"),
             "pointer" => MemoryLocation::Pointer(number),
             "static" => MemoryLocation::Static(number),
-            _ => return Err("Invalid kind"),
+            _ => return Err("unknown segment"),
         })
     }
 }